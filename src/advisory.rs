@@ -0,0 +1,277 @@
+use crate::types::{Triple, bounds};
+use crate::{Change, Dependency};
+use semver::{Version, VersionReq};
+use smartstring::alias::String as SmolString;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// The error returned when loading advisories.
+#[derive(Debug, thiserror::Error)]
+#[allow(missing_docs)]
+pub enum Error {
+    #[error("Couldn't read {path:?}")]
+    Io {
+        source: std::io::Error,
+        path: PathBuf,
+    },
+    #[error("Couldn't parse the advisory in {path:?}")]
+    Parse {
+        source: toml::de::Error,
+        path: PathBuf,
+    },
+}
+
+/// A security advisory for a crate, as stored in the [RustSec advisory database](https://github.com/rustsec/advisory-db).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Advisory {
+    /// The identifier of the advisory, like `RUSTSEC-2019-0001`.
+    pub id: String,
+    /// The name of the affected crate.
+    pub package: String,
+    /// The title of the advisory, if it has one.
+    pub title: Option<String>,
+    /// The kind of informational advisory, like `unmaintained`, or `None` if this is a vulnerability.
+    pub informational: Option<String>,
+    /// Versions which have the problem fixed.
+    pub patched: Vec<VersionReq>,
+    /// Versions which never had the problem.
+    pub unaffected: Vec<VersionReq>,
+}
+
+impl Advisory {
+    /// Return `true` if `version` of the affected crate is neither patched nor unaffected.
+    pub fn is_vulnerable(&self, version: &Version) -> bool {
+        !self
+            .patched
+            .iter()
+            .chain(&self.unaffected)
+            .any(|req| req.matches(version))
+    }
+
+    /// Return `true` if `req` matches at least one version that [is vulnerable](Self::is_vulnerable()), so depending
+    /// on the affected crate with `req` can resolve to such a version.
+    ///
+    /// Pre-releases are ignored, and versions which were never published are considered as well.
+    pub fn affects(&self, req: &VersionReq) -> bool {
+        let Some((mut lowest_vulnerable, upper)) = bounds(req) else {
+            return true;
+        };
+        let mut safe: Vec<(Triple, Triple)> = self
+            .patched
+            .iter()
+            .chain(&self.unaffected)
+            .filter_map(bounds)
+            .collect();
+        safe.sort();
+        for (lower, safe_upper) in safe {
+            if lowest_vulnerable >= upper {
+                return false;
+            }
+            if lower > lowest_vulnerable {
+                return true;
+            }
+            lowest_vulnerable = lowest_vulnerable.max(safe_upper);
+        }
+        lowest_vulnerable < upper
+    }
+}
+
+/// A newly added crate version with a dependency that can resolve to a version with an advisory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Alert<'db> {
+    /// The name of the crate of the new version.
+    pub crate_name: SmolString,
+    /// The new version.
+    pub crate_version: SmolString,
+    /// The dependency of the new version that is affected by `advisory`.
+    pub dependency: Dependency,
+    /// The advisory for the crate of the dependency.
+    pub advisory: &'db Advisory,
+}
+
+/// A collection of advisories, typically loaded from a local clone of the RustSec advisory database.
+#[derive(Debug, Default)]
+pub struct Database {
+    by_package: HashMap<String, Vec<Advisory>>,
+    errors: Vec<Error>,
+}
+
+impl Database {
+    /// Load all advisories from the directory at `path` and all directories below it, like a clone of the RustSec
+    /// advisory database.
+    ///
+    /// Advisories are Markdown files with TOML front matter in a code block, or TOML files in older versions of the database.
+    /// Other files, as well as withdrawn advisories, are skipped.
+    ///
+    /// Files that can't be read or parsed are skipped as well, and their errors are available through [`errors()`](Self::errors()),
+    /// so only failing to read a directory fails the whole load.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let mut db = Database::default();
+        let mut dirs = vec![path.as_ref().to_owned()];
+        while let Some(dir) = dirs.pop() {
+            let io_err = |source| Error::Io {
+                source,
+                path: dir.clone(),
+            };
+            for entry in std::fs::read_dir(&dir).map_err(io_err)? {
+                let path = entry.map_err(io_err)?.path();
+                if path
+                    .file_name()
+                    .is_some_and(|name| name.to_string_lossy().starts_with('.'))
+                {
+                    continue;
+                }
+                if path.is_dir() {
+                    dirs.push(path);
+                    continue;
+                }
+                let is_markdown = match path.extension().and_then(|ext| ext.to_str()) {
+                    Some("md") => true,
+                    Some("toml") => false,
+                    _ => continue,
+                };
+                let content = match std::fs::read_to_string(&path) {
+                    Ok(content) => content,
+                    Err(source) => {
+                        db.errors.push(Error::Io { source, path });
+                        continue;
+                    }
+                };
+                match parse(&content, is_markdown) {
+                    Ok(Some(advisory)) => db.insert(advisory),
+                    Ok(None) => {}
+                    Err(source) => db.errors.push(Error::Parse { source, path }),
+                }
+            }
+        }
+        Ok(db)
+    }
+
+    /// Add `advisory` to the database.
+    pub fn insert(&mut self, advisory: Advisory) {
+        self.by_package
+            .entry(advisory.package.clone())
+            .or_default()
+            .push(advisory);
+    }
+
+    /// Return the errors of all advisory files that were skipped by [`load()`](Self::load()) as they couldn't be read or parsed.
+    pub fn errors(&self) -> &[Error] {
+        &self.errors
+    }
+
+    /// Return all advisories for the crate `package`.
+    pub fn advisories(&self, package: &str) -> &[Advisory] {
+        self.by_package.get(package).map_or(&[], Vec::as_slice)
+    }
+
+    /// Return an alert for each dependency of the versions added by `changes` that [is affected](Advisory::affects())
+    /// by an advisory.
+    ///
+    /// Yanked versions are skipped, as they can't be selected by new dependents anymore, and so are dependencies with
+    /// invalid version requirements.
+    /// [Informational](Advisory::informational) advisories, like those about unmaintained crates, are skipped as well as they
+    /// don't name vulnerable versions.
+    pub fn check<'a>(&self, changes: impl IntoIterator<Item = &'a Change>) -> Vec<Alert<'_>> {
+        let mut alerts = Vec::new();
+        let added = changes.into_iter().flat_map(|change| match change {
+            Change::Added { .. } | Change::CrateCreated { .. } => change.versions(),
+            _ => &[],
+        });
+        for version in added.filter(|version| !version.yanked) {
+            for dependency in &version.dependencies {
+                let package = dependency.package.as_deref().unwrap_or(&dependency.name);
+                let advisories = self.advisories(package);
+                if advisories.is_empty() {
+                    continue;
+                }
+                let Ok(req) = VersionReq::parse(&dependency.required_version) else {
+                    continue;
+                };
+                for advisory in advisories
+                    .iter()
+                    .filter(|a| a.informational.is_none() && a.affects(&req))
+                {
+                    alerts.push(Alert {
+                        crate_name: version.name.clone(),
+                        crate_version: version.version.clone(),
+                        dependency: dependency.clone(),
+                        advisory,
+                    });
+                }
+            }
+        }
+        alerts
+    }
+}
+
+/// The TOML front matter of an advisory, or the whole file in older versions of the database.
+#[derive(serde::Deserialize)]
+struct FrontMatter {
+    advisory: Metadata,
+    #[serde(default)]
+    versions: Versions,
+}
+
+/// The `[advisory]` table of an advisory.
+#[derive(serde::Deserialize)]
+struct Metadata {
+    id: String,
+    package: String,
+    title: Option<String>,
+    informational: Option<String>,
+    withdrawn: Option<toml::Value>,
+}
+
+/// The `[versions]` table of an advisory.
+#[derive(Default, serde::Deserialize)]
+struct Versions {
+    #[serde(default)]
+    patched: Vec<VersionReq>,
+    #[serde(default)]
+    unaffected: Vec<VersionReq>,
+}
+
+/// Parse the advisory in `content`, or return `None` if it isn't one or if it was withdrawn.
+///
+/// TOML files without an `[advisory]` table aren't advisories, but Markdown files with TOML front matter must be.
+fn parse(content: &str, is_markdown: bool) -> Result<Option<Advisory>, toml::de::Error> {
+    let (front_matter, title) = if is_markdown {
+        let mut lines = content.lines();
+        if !lines.any(|line| line.trim_end() == "```toml") {
+            return Ok(None);
+        }
+        let front_matter: Vec<_> = lines
+            .by_ref()
+            .take_while(|line| line.trim_end() != "```")
+            .collect();
+        let title = lines
+            .find_map(|line| line.strip_prefix("# "))
+            .map(|title| title.trim().to_owned());
+        (front_matter.join("\n"), title)
+    } else {
+        (content.to_owned(), None)
+    };
+
+    let front_matter: FrontMatter = if is_markdown {
+        toml::from_str(&front_matter)?
+    } else {
+        let table: toml::Table = toml::from_str(&front_matter)?;
+        if !table.contains_key("advisory") {
+            return Ok(None);
+        }
+        table.try_into()?
+    };
+    let FrontMatter { advisory, versions } = front_matter;
+    if advisory.withdrawn.is_some() {
+        return Ok(None);
+    }
+    Ok(Some(Advisory {
+        id: advisory.id,
+        package: advisory.package,
+        title: title.or(advisory.title),
+        informational: advisory.informational,
+        patched: versions.patched,
+        unaffected: versions.unaffected,
+    }))
+}
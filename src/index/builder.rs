@@ -0,0 +1,428 @@
+use crate::Index;
+use crate::index::init::Error;
+use crate::index::{
+    CLONE_IN_PROGRESS_MARKER, CloneOptions, Config, IncompleteClone, RetryPolicy, UrlMismatch,
+};
+use bstr::{BStr, BString, ByteSlice};
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicBool;
+use std::time::Duration;
+
+/// A builder for [`Index`] instances with fully owned configuration.
+///
+/// # Examples
+///
+/// ```no_run
+/// use std::sync::atomic::AtomicBool;
+/// use crates_index_diff::index::IndexBuilder;
+///
+/// # let path = tempdir::TempDir::new("index").unwrap();
+/// let index = IndexBuilder::new(path.path())
+///     .url("https://github.com/rust-lang/staging.crates.io-index")
+///     .seen_ref_name(format!("refs/heads/{}-last-seen", "tenant"))
+///     .build(gix::progress::Discard, &AtomicBool::default())?;
+/// # Ok::<(), crates_index_diff::index::init::Error>(())
+/// ```
+pub struct IndexBuilder {
+    path: PathBuf,
+    clone_options: CloneOptions,
+    /// The branch to fetch, or `None` to detect and follow the default branch of the remote.
+    branch_name: Option<String>,
+    /// The configuration of the index, whose branch is set when building.
+    config: Config,
+}
+
+/// Configuration
+impl IndexBuilder {
+    /// Create a new builder for an index at `path`, which will be cloned if it doesn't exist yet.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        IndexBuilder {
+            path: path.into(),
+            clone_options: CloneOptions::default(),
+            branch_name: None,
+            config: Config::default(),
+        }
+    }
+
+    /// Set the url to clone the crates-index repository from if it doesn't exist yet.
+    pub fn url(mut self, url: impl Into<String>) -> Self {
+        self.clone_options.url = url.into();
+        self
+    }
+
+    /// Set how much history to clone if the repository doesn't exist yet, see [`CloneOptions::shallow`].
+    pub fn shallow(mut self, shallow: gix::remote::fetch::Shallow) -> Self {
+        self.clone_options.shallow = shallow;
+        self
+    }
+
+    /// Set all options to use when cloning the crates-index repository.
+    pub fn clone_options(mut self, options: CloneOptions) -> Self {
+        self.clone_options = options;
+        self
+    }
+
+    /// Set the name of the branch to fetch, like `master`.
+    ///
+    /// If unset, the default branch of the remote is detected and followed, see [`Config::follow_remote_head`].
+    pub fn branch_name(mut self, name: impl Into<String>) -> Self {
+        self.branch_name = Some(name.into());
+        self
+    }
+
+    /// Set the symbolic name of the remote to fetch from, like `origin`.
+    ///
+    /// If unset, the first configured remote is used.
+    pub fn remote_name(mut self, name: impl Into<BString>) -> Self {
+        self.config.remote_name = Some(name.into());
+        self
+    }
+
+    /// Set the full name of the reference to keep track of the last seen state, like `refs/heads/crates-index-diff_last-seen`.
+    pub fn seen_ref_name(mut self, name: impl Into<String>) -> Self {
+        self.config.seen_ref_name = name.into();
+        self
+    }
+
+    /// Set the size of the object cache in bytes, with `0` disabling it.
+    ///
+    /// If unset, it defaults to 4MB unless configured in the git configuration.
+    pub fn object_cache_size(mut self, bytes: usize) -> Self {
+        self.config.object_cache_size = Some(bytes);
+        self
+    }
+
+    /// If `true` (the default), use the GitHub API to learn if there are changes at all before fetching.
+    pub fn github_fast_path(mut self, enabled: bool) -> Self {
+        self.config.github_fast_path = enabled;
+        self
+    }
+
+    /// If `true`, also fetch the `snapshot-*` branches, see [`Config::fetch_snapshot_branches`].
+    pub fn fetch_snapshot_branches(mut self, enabled: bool) -> Self {
+        self.config.fetch_snapshot_branches = enabled;
+        self
+    }
+
+    /// Set the longest time to wait for other processes to finish fetching, with the default being 5 minutes.
+    pub fn lock_timeout(mut self, timeout: Duration) -> Self {
+        self.config.lock_timeout = timeout;
+        self
+    }
+
+    /// If `true`, obtain changes without network access, see [`Config::offline`].
+    ///
+    /// Note that a repository is still cloned if it doesn't exist yet.
+    pub fn offline(mut self, enabled: bool) -> Self {
+        self.config.offline = enabled;
+        self
+    }
+
+    /// Set how often to retry fetches that fail for reasons that might go away, see [`Config::retry_policy`].
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.config.retry_policy = policy;
+        self
+    }
+
+    /// If `true`, report new crates as a whole, see [`Config::report_crate_creations`].
+    pub fn report_crate_creations(mut self, enabled: bool) -> Self {
+        self.config.report_crate_creations = enabled;
+        self
+    }
+
+    /// If `true`, compare the dependencies of new versions to their predecessor, see [`Config::attach_dependency_changes`].
+    pub fn attach_dependency_changes(mut self, enabled: bool) -> Self {
+        self.config.attach_dependency_changes = enabled;
+        self
+    }
+
+    /// If `true`, compare the features of new versions to their predecessor, see [`Config::attach_feature_changes`].
+    pub fn attach_feature_changes(mut self, enabled: bool) -> Self {
+        self.config.attach_feature_changes = enabled;
+        self
+    }
+}
+
+/// Instantiation
+impl IndexBuilder {
+    /// Validate the configuration and open the index repository, or clone it if it doesn't exist yet.
+    ///
+    /// The `progress` and `should_interrupt` parameters are used to provide progress for the clone and allow
+    /// it to be interrupted gracefully.
+    pub fn build<P>(self, mut progress: P, should_interrupt: &AtomicBool) -> Result<Index, Error>
+    where
+        P: gix::NestedProgress,
+        P::SubProgress: 'static,
+    {
+        self.validate()?;
+        let IndexBuilder {
+            path,
+            clone_options:
+                CloneOptions {
+                    url,
+                    on_url_mismatch,
+                    shallow,
+                    on_incomplete_clone,
+                },
+            branch_name,
+            mut config,
+        } = self;
+        let remote_name = config.remote_name.take();
+
+        let existing = match gix::open(&path) {
+            Ok(repo) => match remote_url_mismatch(&repo, remote_name.as_ref(), &url) {
+                None => Some(repo),
+                Some(actual) => match on_url_mismatch {
+                    UrlMismatch::Error => {
+                        return Err(Error::RemoteUrlMismatch {
+                            expected: url,
+                            actual,
+                        });
+                    }
+                    UrlMismatch::RewriteRemoteUrl => {
+                        let remote_name = remote_name
+                            .clone()
+                            .or_else(|| repo.remote_names().into_iter().next().map(Cow::into_owned))
+                            .unwrap_or_else(|| "origin".into());
+                        set_remote_url(&repo, remote_name.as_bstr(), &url)?;
+                        Some(gix::open(&path)?)
+                    }
+                    UrlMismatch::Reclone => {
+                        remove_clone(repo, &path)?;
+                        None
+                    }
+                },
+            },
+            Err(gix::open::Error::NotARepository { .. }) => None,
+            Err(err) => return Err(err.into()),
+        };
+        let existing = match existing {
+            Some(repo) if is_incomplete_clone(&repo) => match on_incomplete_clone {
+                IncompleteClone::Error => return Err(Error::IncompleteClone { path }),
+                IncompleteClone::Resume
+                    if resume_clone(
+                        &repo,
+                        remote_name.as_ref(),
+                        &mut progress,
+                        should_interrupt,
+                    )? =>
+                {
+                    Some(repo)
+                }
+                IncompleteClone::Resume | IncompleteClone::Reclone => {
+                    remove_clone(repo, &path)?;
+                    None
+                }
+            },
+            existing => existing,
+        };
+        let mut repo = match existing {
+            Some(repo) => repo,
+            None => clone_bare(url, shallow, &path, progress, should_interrupt)?,
+        };
+
+        config.configure_object_cache(&mut repo);
+        config.remote_name =
+            remote_name.or_else(|| repo.remote_names().into_iter().next().map(Cow::into_owned));
+        config.follow_remote_head = branch_name.is_none();
+        config.branch_name = branch_name
+            .or_else(|| {
+                default_branch_name(&repo, config.remote_name.as_ref().map(|n| n.as_bstr()))
+            })
+            .unwrap_or_else(|| "master".into());
+        Ok(Index { config, repo })
+    }
+
+    /// Like [`Self::build()`], but clones on the blocking thread pool of the `tokio` runtime and returns
+    /// an index that can be shared across threads.
+    ///
+    /// Dropping the returned future interrupts an ongoing clone.
+    #[cfg(feature = "async")]
+    pub async fn build_async(self) -> Result<crate::ThreadSafeIndex, Error> {
+        crate::index::spawn_blocking(move |should_interrupt| {
+            self.build(gix::progress::Discard, should_interrupt)
+                .map(Index::into_sync)
+        })
+        .await
+    }
+
+    fn validate(&self) -> Result<(), Error> {
+        let invalid = |name: &str, source| Error::InvalidReferenceName {
+            name: name.into(),
+            source,
+        };
+        gix::validate::reference::name(self.config.seen_ref_name.as_str().into())
+            .map_err(|err| invalid(&self.config.seen_ref_name, err))?;
+        let branch_ref = self
+            .branch_name
+            .as_ref()
+            .map(|name| format!("refs/heads/{name}"));
+        if let Some(branch_ref) = &branch_ref {
+            gix::validate::reference::branch_name(branch_ref.as_str().into())
+                .map_err(|err| invalid(branch_ref, err))?;
+        }
+        if let Some(remote_name) = &self.config.remote_name {
+            gix::validate::reference::name_partial(remote_name.as_bstr())
+                .map_err(|err| invalid(&remote_name.to_string(), err))?;
+        }
+
+        let conflicts_with_fetched_refs = !self.config.seen_ref_name.starts_with("refs/")
+            || branch_ref.as_ref() == Some(&self.config.seen_ref_name)
+            || self.config.seen_ref_name.starts_with("refs/remotes/");
+        if conflicts_with_fetched_refs {
+            return Err(Error::SeenReferenceConflict {
+                name: self.config.seen_ref_name.clone(),
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Detect the default branch of the remote from the local `refs/remotes/<remote>/HEAD` as written when cloning,
+/// or from the `HEAD` of a bare clone, which points to the default branch of the remote at the time of cloning.
+fn default_branch_name(repo: &gix::Repository, remote_name: Option<&BStr>) -> Option<String> {
+    let from_remote_head = remote_name.and_then(|remote| remote_head_branch_name(repo, remote));
+    from_remote_head.or_else(|| {
+        repo.head_name()
+            .ok()??
+            .as_bstr()
+            .strip_prefix(b"refs/heads/")?
+            .to_str()
+            .ok()
+            .map(ToOwned::to_owned)
+    })
+}
+
+/// Return the name of the branch that `refs/remotes/<remote>/HEAD` points to, if it exists.
+pub(crate) fn remote_head_branch_name(repo: &gix::Repository, remote: &BStr) -> Option<String> {
+    let head = repo
+        .try_find_reference(format!("refs/remotes/{remote}/HEAD").as_str())
+        .ok()??;
+    let prefix = format!("refs/remotes/{remote}/");
+    head.target()
+        .try_name()?
+        .as_bstr()
+        .strip_prefix(prefix.as_bytes())?
+        .to_str()
+        .ok()
+        .map(ToOwned::to_owned)
+}
+
+fn clone_bare<P>(
+    url: String,
+    shallow: gix::remote::fetch::Shallow,
+    path: &Path,
+    progress: P,
+    should_interrupt: &AtomicBool,
+) -> Result<gix::Repository, Error>
+where
+    P: gix::NestedProgress,
+    P::SubProgress: 'static,
+{
+    let mut prepare = gix::prepare_clone_bare(url, path)?.with_shallow(shallow);
+    // The repository is bare, so `path` is its git directory.
+    let marker = path.join(CLONE_IN_PROGRESS_MARKER);
+    std::fs::write(&marker, b"")?;
+    let (repo, _out) = prepare.fetch_only(progress, should_interrupt)?;
+    std::fs::remove_file(marker)?;
+    Ok(repo)
+}
+
+/// Return `true` if `repo` was cloned by us, but the clone was interrupted before it completed.
+/// Delete `repo` to clone it anew, but only if it's a bare repository that is entirely contained in `path`,
+/// so nothing but the clone can be lost.
+fn remove_clone(repo: gix::Repository, path: &Path) -> Result<(), Error> {
+    let is_clone = repo.is_bare()
+        && gix::path::realpath(repo.git_dir()).ok() == gix::path::realpath(path).ok();
+    drop(repo);
+    if !is_clone {
+        return Err(Error::RefuseToRemove {
+            path: path.to_owned(),
+        });
+    }
+    std::fs::remove_dir_all(path)?;
+    Ok(())
+}
+
+fn is_incomplete_clone(repo: &gix::Repository) -> bool {
+    repo.git_dir().join(CLONE_IN_PROGRESS_MARKER).exists()
+}
+
+/// Fetch everything that is missing in the incomplete clone at `repo` from its remote,
+/// or return `false` if there is no remote to fetch from.
+fn resume_clone<P>(
+    repo: &gix::Repository,
+    remote_name: Option<&BString>,
+    progress: &mut P,
+    should_interrupt: &AtomicBool,
+) -> Result<bool, Error>
+where
+    P: gix::NestedProgress,
+    P::SubProgress: 'static,
+{
+    let remote = match remote_name {
+        Some(name) => repo.find_remote(name.as_bstr()).ok(),
+        None => repo
+            .find_default_remote(gix::remote::Direction::Fetch)
+            .and_then(Result::ok),
+    };
+    let Some(remote) = remote.filter(|r| {
+        r.url(gix::remote::Direction::Fetch).is_some()
+            && !r.refspecs(gix::remote::Direction::Fetch).is_empty()
+    }) else {
+        return Ok(false);
+    };
+    remote
+        .connect(gix::remote::Direction::Fetch)?
+        .prepare_fetch(&mut *progress, Default::default())?
+        .receive(&mut *progress, should_interrupt)?;
+    std::fs::remove_file(repo.git_dir().join(CLONE_IN_PROGRESS_MARKER))?;
+    Ok(true)
+}
+
+/// Return `Some(actual_url)` if the remote to fetch from doesn't fetch from `expected_url`.
+fn remote_url_mismatch(
+    repo: &gix::Repository,
+    remote_name: Option<&BString>,
+    expected_url: &str,
+) -> Option<Option<BString>> {
+    let actual = match remote_name {
+        Some(name) => repo.find_remote(name.as_bstr()).ok(),
+        None => repo
+            .find_default_remote(gix::remote::Direction::Fetch)
+            .and_then(Result::ok),
+    }
+    .and_then(|remote| remote.url(gix::remote::Direction::Fetch).cloned());
+    let normalized = |url: &gix::Url| {
+        // Local paths are made absolute when cloning, so relative paths only match once resolved.
+        if url.scheme == gix::url::Scheme::File
+            && let Ok(path) = std::fs::canonicalize(gix::path::from_bstr(url.path.as_bstr()))
+        {
+            return gix::path::into_bstr(path).to_vec();
+        }
+        let url = url.to_bstring();
+        let url = url.trim_end_with(|c| c == '/');
+        url.strip_suffix(b".git").unwrap_or(url).to_owned()
+    };
+    match (&actual, gix::Url::try_from(expected_url)) {
+        (Some(actual), Ok(expected)) if normalized(actual) == normalized(&expected) => None,
+        _ => Some(actual.map(|url| url.to_bstring())),
+    }
+}
+
+/// Persist `url` as url of the remote named `remote_name` in the configuration of `repo`.
+fn set_remote_url(repo: &gix::Repository, remote_name: &BStr, url: &str) -> Result<(), Error> {
+    let config_path = repo.common_dir().join("config");
+    let mut config =
+        gix::config::File::from_path_no_includes(config_path.clone(), gix::config::Source::Local)?;
+    config.set_raw_value_by("remote", Some(remote_name), "url", url)?;
+    let mut out = gix::lock::File::acquire_to_update_resource(
+        config_path,
+        gix::lock::acquire::Fail::Immediately,
+        None,
+    )?;
+    config.write_to(&mut out)?;
+    out.commit().map_err(|err| err.error)?;
+    Ok(())
+}
@@ -0,0 +1,290 @@
+use crate::Index;
+use crate::index::diff;
+use bstr::{BString, ByteSlice};
+use gix::lock::acquire::Fail;
+use gix::odb::pack::data::output;
+use gix::prelude::ObjectIdExt;
+use std::io::{BufRead, Write};
+use std::path::Path;
+use std::sync::atomic::AtomicBool;
+
+/// The error returned when importing or exporting bundles.
+#[derive(Debug, thiserror::Error)]
+#[allow(missing_docs)]
+pub enum Error {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error("Invalid line in bundle header: {line:?}")]
+    InvalidHeader { line: BString },
+    #[error("The bundle requires the unsupported capability {capability:?}")]
+    UnsupportedCapability { capability: BString },
+    #[error("The bundle requires {} commit(s) that aren't present in the repository, like {}", ids.len(), ids[0])]
+    MissingPrerequisites { ids: Vec<gix::hash::ObjectId> },
+    #[error("The bundle doesn't contain the branch {name:?}, but only {refs:?}")]
+    MissingBranch { name: String, refs: Vec<BString> },
+    #[error("The bundle didn't contain the commit {id} its branch points to")]
+    MissingTip { id: gix::hash::ObjectId },
+    #[error("There is no remote tracking branch that branch {name:?} could be imported into")]
+    NoTrackingBranch { name: String },
+    #[error("Couldn't write the pack contained in the bundle")]
+    WritePack(#[from] Box<gix::odb::pack::bundle::write::Error>),
+    #[error("Couldn't determine the objects to put into the bundle")]
+    CountObjects(#[from] Box<output::count::objects::Error>),
+    #[error("Couldn't write the pack of the bundle")]
+    WriteBundle(#[from] Box<output::bytes::Error<output::entry::iter_from_counts::Error>>),
+    #[error("Couldn't update the remote tracking branch")]
+    ReferenceEdit(#[from] Box<gix::reference::edit::Error>),
+    #[error(transparent)]
+    Index(#[from] Box<diff::Error>),
+}
+
+impl_from_boxed!(gix::odb::pack::bundle::write::Error => Error::WritePack);
+impl_from_boxed!(output::count::objects::Error => Error::CountObjects);
+impl_from_boxed!(output::bytes::Error<output::entry::iter_from_counts::Error> => Error::WriteBundle);
+impl_from_boxed!(gix::reference::edit::Error => Error::ReferenceEdit);
+impl_from_boxed!(diff::Error => Error::Index);
+
+/// The header of a bundle, which is followed by a pack.
+struct Header {
+    /// The commits the receiver must have to be able to use the pack.
+    prerequisites: Vec<gix::hash::ObjectId>,
+    /// The references stored in the bundle, along with the commit they point to.
+    refs: Vec<(gix::hash::ObjectId, BString)>,
+}
+
+/// Bundles
+impl Index {
+    /// As `import_bundle_with_options()`, but without the options.
+    pub fn import_bundle(&self, path: impl AsRef<Path>) -> Result<gix::hash::ObjectId, Error> {
+        self.import_bundle_with_options(path, gix::progress::Discard, &AtomicBool::default())
+    }
+
+    /// Import the git bundle at `path`, as created with `git bundle create` or [`Self::export_bundle()`], and
+    /// set the remote tracking branch to the commit the bundle stores for [`Config::branch_name`](crate::index::Config::branch_name), returning that commit.
+    ///
+    /// This is the equivalent of [`Self::fetch_only()`] for machines that can't reach the remote, so afterwards
+    /// the changes can be obtained as if they were fetched.
+    /// Set [`Config::offline`](crate::index::Config::offline) to prevent [`Self::peek_changes()`] and [`Self::fetch_changes()`] from fetching again,
+    /// which would also fail without network access.
+    ///
+    /// All commits the bundle requires must be present in the repository already.
+    pub fn import_bundle_with_options<P>(
+        &self,
+        path: impl AsRef<Path>,
+        mut progress: P,
+        should_interrupt: &AtomicBool,
+    ) -> Result<gix::hash::ObjectId, Error>
+    where
+        P: gix::NestedProgress,
+        P::SubProgress: 'static,
+    {
+        let _lock = self
+            .lock(Fail::AfterDurationWithBackoff(self.config.lock_timeout))
+            .map_err(Box::new)?;
+        let repo = &self.repo;
+        let mut input = std::io::BufReader::new(std::fs::File::open(path)?);
+        let header = read_header(&mut input, repo.object_hash())?;
+
+        let missing: Vec<_> = header
+            .prerequisites
+            .into_iter()
+            .filter(|id| !repo.has_object(id))
+            .collect();
+        if !missing.is_empty() {
+            return Err(Error::MissingPrerequisites { ids: missing });
+        }
+        let branch = format!("refs/heads/{}", self.branch_name());
+        let Some(tip) = header
+            .refs
+            .iter()
+            .find_map(|(id, name)| (*name == branch).then_some(*id))
+        else {
+            return Err(Error::MissingBranch {
+                name: branch,
+                refs: header.refs.into_iter().map(|(_, name)| name).collect(),
+            });
+        };
+        let tracking_name = self
+            .tracking_branch_name()
+            .map_err(Box::new)?
+            .ok_or_else(|| Error::NoTrackingBranch {
+                name: branch.clone(),
+            })?;
+
+        let outcome = gix::odb::pack::Bundle::write_to_directory(
+            &mut input,
+            Some(&repo.objects.store_ref().path().join("pack")),
+            &mut progress,
+            should_interrupt,
+            Some(repo.objects.clone()),
+            gix::odb::pack::bundle::write::Options {
+                object_hash: repo.object_hash(),
+                ..Default::default()
+            },
+        )?;
+        if !repo.has_object(tip) {
+            return Err(Error::MissingTip { id: tip });
+        }
+        repo.reference(
+            tracking_name.as_bstr().to_str_lossy().as_ref(),
+            tip,
+            gix::refs::transaction::PreviousValue::Any,
+            format!("importing {branch} from bundle"),
+        )?;
+        if let Some(keep_path) = outcome.keep_path {
+            std::fs::remove_file(keep_path)?;
+        }
+        Ok(tip)
+    }
+
+    /// As `export_bundle_with_options()`, but without the options.
+    pub fn export_bundle(&self, path: impl AsRef<Path>) -> Result<gix::hash::ObjectId, Error> {
+        self.export_bundle_with_options(path, gix::progress::Discard, &AtomicBool::default())
+    }
+
+    /// Write a git bundle to `path` which contains all commits that were fetched into the remote tracking branch
+    /// of [`Config::branch_name`](crate::index::Config::branch_name), but aren't reachable from the [`Self::last_seen_reference()`], and return the commit
+    /// the remote tracking branch points to.
+    ///
+    /// The bundle stores the remote tracking branch as [`Config::branch_name`](crate::index::Config::branch_name), and can be imported with
+    /// [`Self::import_bundle()`] into an index whose last-seen commit is the same as the one of this instance,
+    /// or with `git fetch`.
+    /// Without a last-seen commit, all commits are exported.
+    pub fn export_bundle_with_options<P>(
+        &self,
+        path: impl AsRef<Path>,
+        mut progress: P,
+        should_interrupt: &AtomicBool,
+    ) -> Result<gix::hash::ObjectId, Error>
+    where
+        P: gix::NestedProgress,
+        P::SubProgress: 'static,
+    {
+        let repo = &self.repo;
+        let tip = self.tracking_branch().map_err(Box::new)?.id().detach();
+        let last_seen = self
+            .last_seen_and_from()
+            .map_err(Box::new)?
+            .0
+            .filter(|id| repo.has_object(id));
+        let commits = tip
+            .attach(repo)
+            .ancestors()
+            .with_hidden(last_seen)
+            .all()
+            .map_err(|err| Box::new(diff::Error::from(err)))?
+            .map(|info| {
+                info.map(|info| info.id)
+                    .map_err(|err| Box::new(err) as Box<dyn std::error::Error + Send + Sync>)
+            })
+            .collect::<Vec<_>>();
+
+        let mut db = repo.objects.clone().into_inner();
+        // Required to be able to copy entries from existing packs.
+        db.prevent_pack_unload();
+        db.ignore_replacements = true;
+        let (counts, _outcome) = output::count::objects(
+            db.clone(),
+            Box::new(commits.into_iter()),
+            &progress.add_child("counting objects"),
+            should_interrupt,
+            output::count::objects::Options {
+                input_object_expansion:
+                    output::count::objects::ObjectExpansion::TreeAdditionsComparedToAncestor,
+                ..Default::default()
+            },
+        )?;
+
+        let num_entries = counts.len() as u32;
+        let entries = output::entry::iter_from_counts(
+            counts,
+            db,
+            Box::new(progress.add_child("creating entries")),
+            output::entry::iter_from_counts::Options::default(),
+        );
+        let mut out = std::io::BufWriter::new(std::fs::File::create(path)?);
+        match repo.object_hash() {
+            gix::hash::Kind::Sha1 => writeln!(out, "# v2 git bundle")?,
+            other => writeln!(out, "# v3 git bundle\n@object-format={other}")?,
+        }
+        if let Some(last_seen) = last_seen {
+            writeln!(out, "-{last_seen}")?;
+        }
+        writeln!(out, "{tip} refs/heads/{}\n", self.branch_name())?;
+        for written in output::bytes::FromEntriesIter::new(
+            gix::features::parallel::InOrderIter::from(entries),
+            &mut out,
+            num_entries,
+            gix::odb::pack::data::Version::V2,
+            repo.object_hash(),
+        ) {
+            written?;
+        }
+        out.flush()?;
+        Ok(tip)
+    }
+}
+
+/// Read the header of a version 2 or version 3 bundle from `input`, leaving it positioned at the start of the pack.
+fn read_header(input: &mut impl BufRead, object_hash: gix::hash::Kind) -> Result<Header, Error> {
+    let mut header = Header {
+        prerequisites: Vec::new(),
+        refs: Vec::new(),
+    };
+    let mut line = Vec::new();
+    let mut read_line = |line: &mut Vec<u8>| -> std::io::Result<()> {
+        line.clear();
+        input.read_until(b'\n', line)?;
+        if line.last() == Some(&b'\n') {
+            line.pop();
+        }
+        Ok(())
+    };
+
+    read_line(&mut line)?;
+    let is_v3 = match line.as_slice() {
+        b"# v2 git bundle" => false,
+        b"# v3 git bundle" => true,
+        _ => return Err(Error::InvalidHeader { line: line.into() }),
+    };
+    let mut bundle_object_hash = gix::hash::Kind::Sha1;
+    loop {
+        read_line(&mut line)?;
+        if line.is_empty() {
+            break;
+        }
+        if let Some(capability) = line.strip_prefix(b"@").filter(|_| is_v3) {
+            match capability
+                .strip_prefix(b"object-format=")
+                .and_then(|name| name.to_str().ok()?.parse().ok())
+            {
+                Some(kind) => bundle_object_hash = kind,
+                None => {
+                    return Err(Error::UnsupportedCapability {
+                        capability: capability.into(),
+                    });
+                }
+            }
+            continue;
+        }
+        let (is_prerequisite, rest) = match line.strip_prefix(b"-") {
+            Some(rest) => (true, rest),
+            None => (false, line.as_slice()),
+        };
+        let (hex, name) = rest.split_once_str(" ").unwrap_or((rest, b""));
+        let id = gix::hash::ObjectId::from_hex(hex)
+            .ok()
+            .filter(|id| id.kind() == object_hash && id.kind() == bundle_object_hash)
+            .ok_or_else(|| Error::InvalidHeader {
+                line: line.clone().into(),
+            })?;
+        if is_prerequisite {
+            header.prerequisites.push(id);
+        } else if name.is_empty() {
+            return Err(Error::InvalidHeader { line: line.into() });
+        } else {
+            header.refs.push((id, name.into()));
+        }
+    }
+    Ok(header)
+}
@@ -0,0 +1,79 @@
+use crate::index::diff::{Error, Order, github};
+use crate::index::spawn_blocking;
+use crate::{Change, Index, ThreadSafeIndex};
+use std::sync::atomic::AtomicBool;
+
+/// Find changes without blocking the current thread
+impl ThreadSafeIndex {
+    /// Like [`Index::peek_changes_with_options()`], but performs the fetch and diff on the blocking thread pool
+    /// of the `tokio` runtime.
+    ///
+    /// If the remote is on GitHub, its API is used to learn if there are changes at all without blocking,
+    /// see [`Config::github_fast_path`](crate::index::Config::github_fast_path).
+    /// Dropping the returned future interrupts an ongoing fetch.
+    pub async fn peek_changes_async(
+        &self,
+        order: Order,
+    ) -> Result<(Vec<Change>, gix::hash::ObjectId), Error> {
+        if let Some(last_seen) = self.up_to_date().await? {
+            return Ok((Vec::new(), last_seen));
+        }
+        self.run_blocking(move |index, should_interrupt| {
+            index.peek_changes_with_options(gix::progress::Discard, should_interrupt, order)
+        })
+        .await
+    }
+
+    /// Like [`Index::fetch_changes_with_options()`], but performs the fetch, diff and update of the last-seen reference
+    /// on the blocking thread pool of the `tokio` runtime.
+    ///
+    /// If the remote is on GitHub, its API is used to learn if there are changes at all without blocking,
+    /// see [`Config::github_fast_path`](crate::index::Config::github_fast_path).
+    /// Dropping the returned future interrupts an ongoing fetch.
+    pub async fn fetch_changes_async(&self, order: Order) -> Result<Vec<Change>, Error> {
+        if self.up_to_date().await?.is_some() {
+            return Ok(Vec::new());
+        }
+        self.run_blocking(move |index, should_interrupt| {
+            index.fetch_changes_with_options(gix::progress::Discard, should_interrupt, order)
+        })
+        .await
+    }
+
+    /// Return the last seen commit if the GitHub API indicates that it is still the latest one.
+    async fn up_to_date(&self) -> Result<Option<gix::hash::ObjectId>, Error> {
+        if self.config.offline || !self.config.github_fast_path {
+            return Ok(None);
+        }
+        let (last_seen, from, url, branch) = {
+            let index = self.to_thread_local();
+            let (last_seen, from) = index.last_seen_and_from()?;
+            let (url, _) = index
+                .remote()?
+                .sanitized_url_and_version(gix::remote::Direction::Fetch)?;
+            (last_seen, from, url, index.github_branch().to_owned())
+        };
+        if last_seen != Some(from) {
+            return Ok(None);
+        }
+        Ok(matches!(
+            github::has_changes_async(&url, &from, &branch).await?,
+            github::FastPath::UpToDate
+        )
+        .then_some(from))
+    }
+
+    /// Run `f` with a thread-local [`Index`] on the blocking thread pool, without using the GitHub fast path
+    /// as it was already checked.
+    async fn run_blocking<T>(
+        &self,
+        f: impl FnOnce(Index, &AtomicBool) -> Result<T, Error> + Send + 'static,
+    ) -> Result<T, Error>
+    where
+        T: Send + 'static,
+    {
+        let mut index = self.clone();
+        index.config.github_fast_path = false;
+        spawn_blocking(move |should_interrupt| f(index.to_thread_local(), should_interrupt)).await
+    }
+}
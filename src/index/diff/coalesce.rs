@@ -0,0 +1,278 @@
+use crate::{Change, CrateVersion, DependencyChange, FeatureChange};
+use std::collections::HashMap;
+
+/// The state of a crate version before or after a sequence of changes.
+enum State {
+    /// The version isn't in the index.
+    Absent,
+    /// The version is in the index, like it is described here.
+    Present(Box<CrateVersion>),
+}
+
+/// What happened to a single crate version over a sequence of changes.
+struct Net {
+    /// The state before the first change to the version.
+    before: State,
+    /// The state after the last change to the version.
+    after: State,
+    /// The version as it was seen last, which is the deleted version if it is [`State::Absent`] `after`.
+    last: CrateVersion,
+    /// The dependency and feature changes of the change that added the version last.
+    attached: Attached,
+}
+
+/// The dependency and feature changes that were attached to an `Added` change.
+type Attached = (Option<Vec<DependencyChange>>, Option<Vec<FeatureChange>>);
+
+/// Place-holders for the produced changes, in the order of the first change that affected them.
+enum Slot {
+    /// A change that doesn't affect any version, and is passed through as is.
+    Verbatim(Box<Change>),
+    /// The index into the list of versions that were affected by changes.
+    Version(usize),
+}
+
+/// Collapse `changes`, in the order they happened, into the net effect they have on each crate version.
+///
+/// This makes the outcome the same as if only the start and the end of the sequence were compared, no matter how many
+/// steps there were in between:
+///
+/// * `Added` and `Yanked` become `AddedAndYanked`, and `AddedAndYanked` and `Unyanked` become `Added`.
+/// * `Yanked` and `Unyanked` cancel each other out, and so do `Added` and `VersionDeleted`.
+/// * A version that was deleted and re-added as is disappears, but if it was re-published with a different checksum,
+///   it turns into `VersionDeleted` followed by `Added`.
+/// * Versions deleted by `CrateDeleted` are reported in a single `CrateDeleted` if the crate was deleted last,
+///   and as `VersionDeleted` otherwise.
+/// * Versions that were added are reported in a single `CrateCreated` if the crate was created first,
+///   and as `Added` or `AddedAndYanked` otherwise.
+///
+/// Changes are returned in the order of the first change that affected each version, and
+/// [`Change::Squashed`] is passed through unchanged.
+///
+/// Crates are identified by their lower-cased name, just like their file names in the index.
+pub fn coalesce(changes: impl IntoIterator<Item = Change>) -> Vec<Change> {
+    let mut slots = Vec::new();
+    let mut versions: Vec<Net> = Vec::new();
+    let mut index_by_version = HashMap::<(String, String), usize>::new();
+    let mut crate_deleted_last = HashMap::<String, bool>::new();
+    let mut crate_created_first = HashMap::<String, bool>::new();
+    // The names of crates as reported by `CrateCreated` and `CrateDeleted`.
+    let mut crate_names = HashMap::<String, String>::new();
+
+    let mut record = |version: CrateVersion,
+                      slots: &mut Vec<Slot>,
+                      before: State,
+                      after: bool,
+                      attached: Attached| {
+        let adds = matches!(before, State::Absent);
+        let key = (crate_key(&version.name), version.version.to_string());
+        let after = if after {
+            State::Present(Box::new(version.clone()))
+        } else {
+            State::Absent
+        };
+        match index_by_version.get(&key) {
+            Some(&idx) => {
+                let net = &mut versions[idx];
+                net.after = after;
+                net.last = version;
+                if adds {
+                    net.attached = attached;
+                }
+            }
+            None => {
+                index_by_version.insert(key, versions.len());
+                slots.push(Slot::Version(versions.len()));
+                versions.push(Net {
+                    before,
+                    after,
+                    last: version,
+                    attached,
+                });
+            }
+        }
+    };
+
+    for change in changes {
+        let name = match &change {
+            Change::CrateCreated { name, .. } | Change::CrateDeleted { name, .. } => {
+                Some(name.as_str())
+            }
+            other => other.versions().first().map(|v| v.name.as_str()),
+        };
+        if let Some(key) = name.map(crate_key) {
+            let created = matches!(change, Change::CrateCreated { .. });
+            crate_created_first.entry(key).or_insert(created);
+        }
+        match change {
+            Change::Added {
+                version,
+                dependency_changes,
+                feature_changes,
+            } => {
+                crate_deleted_last.insert(crate_key(&version.name), false);
+                record(
+                    version,
+                    &mut slots,
+                    State::Absent,
+                    true,
+                    (dependency_changes, feature_changes),
+                );
+            }
+            Change::AddedAndYanked(v) => {
+                crate_deleted_last.insert(crate_key(&v.name), false);
+                record(v, &mut slots, State::Absent, true, Default::default());
+            }
+            Change::Yanked(v) => {
+                let before = CrateVersion {
+                    yanked: false,
+                    ..v.clone()
+                };
+                record(
+                    v,
+                    &mut slots,
+                    State::Present(Box::new(before)),
+                    true,
+                    Default::default(),
+                );
+            }
+            Change::Unyanked(v) => {
+                let before = CrateVersion {
+                    yanked: true,
+                    ..v.clone()
+                };
+                record(
+                    v,
+                    &mut slots,
+                    State::Present(Box::new(before)),
+                    true,
+                    Default::default(),
+                );
+            }
+            Change::VersionDeleted(v) => {
+                crate_deleted_last.insert(crate_key(&v.name), false);
+                record(
+                    v.clone(),
+                    &mut slots,
+                    State::Present(Box::new(v)),
+                    false,
+                    Default::default(),
+                );
+            }
+            Change::CrateCreated { name, versions } => {
+                crate_deleted_last.insert(crate_key(&name), false);
+                crate_names.insert(crate_key(&name), name);
+                for v in versions {
+                    record(v, &mut slots, State::Absent, true, Default::default());
+                }
+            }
+            Change::CrateDeleted { name, versions } => {
+                crate_deleted_last.insert(crate_key(&name), true);
+                crate_names.insert(crate_key(&name), name);
+                for v in versions {
+                    record(
+                        v.clone(),
+                        &mut slots,
+                        State::Present(Box::new(v)),
+                        false,
+                        Default::default(),
+                    );
+                }
+            }
+            Change::Squashed { .. } => slots.push(Slot::Verbatim(Box::new(change))),
+        }
+    }
+
+    let added = |v: CrateVersion, (dependency_changes, feature_changes): Attached| {
+        if v.yanked {
+            Change::AddedAndYanked(v)
+        } else {
+            Change::Added {
+                version: v,
+                dependency_changes,
+                feature_changes,
+            }
+        }
+    };
+    let is_set = |flags: &HashMap<String, bool>, key: &str| flags.get(key) == Some(&true);
+    let mut created_by_crate = HashMap::<String, Vec<CrateVersion>>::new();
+    let mut deleted_by_crate = HashMap::<String, Vec<CrateVersion>>::new();
+    let mut out = Vec::with_capacity(slots.len());
+    for slot in &slots {
+        let Slot::Version(idx) = slot else { continue };
+        let net = &versions[*idx];
+        let key = crate_key(&net.last.name);
+        let by_crate = match (&net.before, &net.after) {
+            (State::Absent, State::Present(_)) if is_set(&crate_created_first, &key) => {
+                &mut created_by_crate
+            }
+            (State::Present(_), State::Absent) if is_set(&crate_deleted_last, &key) => {
+                &mut deleted_by_crate
+            }
+            _ => continue,
+        };
+        by_crate.entry(key).or_default().push(net.last.clone());
+    }
+    let crate_name = |key: String| crate_names.get(&key).cloned().unwrap_or(key);
+
+    let mut versions = versions.into_iter().map(Some).collect::<Vec<_>>();
+    for slot in slots {
+        let idx = match slot {
+            Slot::Verbatim(change) => {
+                out.push(*change);
+                continue;
+            }
+            Slot::Version(idx) => idx,
+        };
+        let net = versions[idx]
+            .take()
+            .expect("each version has exactly one slot");
+        match (net.before, net.after) {
+            (State::Absent, State::Absent) => {}
+            (State::Absent, State::Present(v)) => {
+                let key = crate_key(&v.name);
+                if is_set(&crate_created_first, &key) {
+                    if let Some(versions) = created_by_crate.remove(&key) {
+                        out.push(Change::CrateCreated {
+                            name: crate_name(key),
+                            versions,
+                        });
+                    }
+                } else {
+                    out.push(added(*v, net.attached));
+                }
+            }
+            (State::Present(_), State::Absent) => {
+                let key = crate_key(&net.last.name);
+                if is_set(&crate_deleted_last, &key) {
+                    if let Some(versions) = deleted_by_crate.remove(&key) {
+                        out.push(Change::CrateDeleted {
+                            name: crate_name(key),
+                            versions,
+                        });
+                    }
+                } else {
+                    out.push(Change::VersionDeleted(net.last));
+                }
+            }
+            (State::Present(before), State::Present(after)) => {
+                if before.checksum != after.checksum {
+                    out.push(Change::VersionDeleted(*before));
+                    out.push(added(*after, net.attached));
+                } else if before.yanked != after.yanked {
+                    out.push(if after.yanked {
+                        Change::Yanked(*after)
+                    } else {
+                        Change::Unyanked(*after)
+                    });
+                }
+            }
+        }
+    }
+    out
+}
+
+/// Return the key to identify the crate called `name` by, which is the name of its file in the index.
+fn crate_key(name: &str) -> String {
+    name.to_lowercase()
+}
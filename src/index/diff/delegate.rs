@@ -1,5 +1,5 @@
 use crate::index::diff::Error;
-use crate::{Change, CrateVersion};
+use crate::{Change, CrateVersion, Index};
 use ahash::{AHashSet, RandomState};
 use bstr::BStr;
 use hashbrown::HashTable;
@@ -13,9 +13,24 @@ pub(crate) struct Delegate {
     /// All changes that happen within a file, along the line-number it happens in .
     per_file_changes: Vec<(usize, Change)>,
     err: Option<Error>,
+    /// If `true`, added crate files are reported as [`Change::CrateCreated`].
+    report_crate_creations: bool,
+    /// If `true`, the dependencies of versions added to existing crate files are compared to their predecessor.
+    attach_dependency_changes: bool,
+    /// If `true`, the features of versions added to existing crate files are compared to their predecessor.
+    attach_feature_changes: bool,
 }
 
 impl Delegate {
+    pub fn new(index: &Index) -> Self {
+        Delegate {
+            report_crate_creations: index.config.report_crate_creations,
+            attach_dependency_changes: index.config.attach_dependency_changes,
+            attach_feature_changes: index.config.attach_feature_changes,
+            ..Default::default()
+        }
+    }
+
     pub fn handle(
         &mut self,
         change: gix::object::tree::diff::Change<'_, '_, '_>,
@@ -47,14 +62,29 @@ impl Delegate {
                 ..
             } => {
                 if let Some(obj) = entry_data(entry_mode.kind(), id)? {
-                    for line in obj.data.lines() {
-                        let version = version_from_json_line(line, location)?;
-                        let change = if version.yanked {
-                            Change::AddedAndYanked(version)
-                        } else {
-                            Change::Added(version)
-                        };
-                        self.changes.push(change)
+                    if self.report_crate_creations {
+                        let mut added = Vec::with_capacity(obj.data.lines().count());
+                        for line in obj.data.lines() {
+                            added.push(version_from_json_line(line, location)?);
+                        }
+                        self.changes.push(Change::CrateCreated {
+                            name: location.to_string(),
+                            versions: added,
+                        });
+                    } else {
+                        for line in obj.data.lines() {
+                            let version = version_from_json_line(line, location)?;
+                            let change = if version.yanked {
+                                Change::AddedAndYanked(version)
+                            } else {
+                                Change::Added {
+                                    version,
+                                    dependency_changes: None,
+                                    feature_changes: None,
+                                }
+                            };
+                            self.changes.push(change)
+                        }
                     }
                 }
             }
@@ -134,11 +164,33 @@ impl Delegate {
                                 .push((line.0, Change::VersionDeleted(old_version))),
                         }
                     }
+                    let previous = if (self.attach_dependency_changes
+                        || self.attach_feature_changes)
+                        && !new_versions.is_empty()
+                    {
+                        old.data
+                            .lines()
+                            .map(|line| version_from_json_line(line, location))
+                            .collect::<Result<Vec<_>, _>>()?
+                    } else {
+                        Vec::new()
+                    };
                     for (number, version) in new_versions.drain() {
                         let change = if version.yanked {
                             Change::AddedAndYanked(version)
                         } else {
-                            Change::Added(version)
+                            let predecessor = predecessor(&version, &previous);
+                            Change::Added {
+                                dependency_changes: predecessor
+                                    .filter(|_| self.attach_dependency_changes)
+                                    .map(|predecessor| {
+                                        version.dependency_changes_since(predecessor)
+                                    }),
+                                feature_changes: predecessor
+                                    .filter(|_| self.attach_feature_changes)
+                                    .map(|predecessor| version.feature_changes_since(predecessor)),
+                                version,
+                            }
                         };
                         self.per_file_changes.push((number, change));
                     }
@@ -185,10 +237,33 @@ impl<'a> Deref for Line<'a> {
     }
 }
 
-fn version_from_json_line(line: &[u8], file_name: &BStr) -> Result<CrateVersion, Error> {
+pub(crate) fn version_from_json_line(line: &[u8], file_name: &BStr) -> Result<CrateVersion, Error> {
     serde_json::from_slice(line).map_err(|err| Error::VersionDecode {
         source: err,
         file_name: file_name.into(),
         line: line.into(),
     })
 }
+
+/// Return the highest of the `previous` versions that is below `version`, if there is one.
+#[cfg(feature = "semver")]
+fn predecessor<'a>(
+    version: &CrateVersion,
+    previous: &'a [CrateVersion],
+) -> Option<&'a CrateVersion> {
+    let current = semver::Version::parse(&version.version).ok()?;
+    previous
+        .iter()
+        .filter_map(|prev| Some((semver::Version::parse(&prev.version).ok()?, prev)))
+        .filter(|(prev_version, _)| *prev_version < current)
+        .max_by(|a, b| a.0.cmp(&b.0))
+        .map(|(_, prev)| prev)
+}
+
+#[cfg(not(feature = "semver"))]
+fn predecessor<'a>(
+    _version: &CrateVersion,
+    _previous: &'a [CrateVersion],
+) -> Option<&'a CrateVersion> {
+    None
+}
@@ -46,16 +46,11 @@ pub(crate) fn has_changes(
     last_seen_reference: &gix::ObjectId,
     branch_name: &str,
 ) -> Result<FastPath, reqwest::Error> {
-    let (username, repository) = match user_and_repo_from_url_if_github(fetch_url) {
+    let url = match commit_api_url(fetch_url, branch_name) {
         Some(url) => url,
         None => return Ok(FastPath::Indeterminate),
     };
 
-    let url = format!(
-        "https://api.github.com/repos/{}/{}/commits/{}",
-        username, repository, branch_name,
-    );
-
     let client = reqwest::blocking::Client::builder()
         .user_agent("crates-index-diff")
         .build()?;
@@ -65,16 +60,53 @@ pub(crate) fn has_changes(
         .header("If-None-Match", format!("\"{}\"", last_seen_reference))
         .send()?;
 
-    let status = response.status();
+    Ok(fast_path_from_status(response.status()))
+}
+
+/// Like [`has_changes()`], but without blocking the current thread.
+#[cfg(feature = "async")]
+pub(crate) async fn has_changes_async(
+    fetch_url: &gix::Url,
+    last_seen_reference: &gix::ObjectId,
+    branch_name: &str,
+) -> Result<FastPath, reqwest::Error> {
+    let url = match commit_api_url(fetch_url, branch_name) {
+        Some(url) => url,
+        None => return Ok(FastPath::Indeterminate),
+    };
+
+    let client = reqwest::Client::builder()
+        .user_agent("crates-index-diff")
+        .build()?;
+    let response = client
+        .get(&url)
+        .header("Accept", "application/vnd.github.sha")
+        .header("If-None-Match", format!("\"{}\"", last_seen_reference))
+        .send()
+        .await?;
+
+    Ok(fast_path_from_status(response.status()))
+}
+
+/// Return the API url to query the commit `branch_name` points to, if `fetch_url` is on GitHub.
+fn commit_api_url(fetch_url: &gix::Url, branch_name: &str) -> Option<String> {
+    let (username, repository) = user_and_repo_from_url_if_github(fetch_url)?;
+    Some(format!(
+        "https://api.github.com/repos/{}/{}/commits/{}",
+        username, repository, branch_name,
+    ))
+}
+
+fn fast_path_from_status(status: StatusCode) -> FastPath {
     if status == StatusCode::NOT_MODIFIED {
-        Ok(FastPath::UpToDate)
+        FastPath::UpToDate
     } else if status.is_success() {
-        Ok(FastPath::NeedsFetch)
+        FastPath::NeedsFetch
     } else {
         // Usually response_code == 404 if the repository does not exist, and
         // response_code == 422 if exists but GitHub is unable to resolve the
         // requested rev.
-        Ok(FastPath::Indeterminate)
+        FastPath::Indeterminate
     }
 }
 
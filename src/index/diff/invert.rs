@@ -0,0 +1,49 @@
+use crate::{Change, CrateVersion};
+
+/// Invert `changes`, in the order they happened, so they undo what they did.
+///
+/// If `changes` are the changes from `A` to `B`, the returned changes are those from `B` to `A`, in reverse order:
+///
+/// * `Added` and `AddedAndYanked` become `VersionDeleted`, and vice versa.
+/// * `Yanked` becomes `Unyanked`, and vice versa.
+/// * `CrateCreated` becomes `CrateDeleted`, and `CrateDeleted` becomes an `Added` or `AddedAndYanked`
+///   for each of its versions.
+/// * `Squashed` swaps its commits, and remains the first change.
+///
+/// Note that versions which are deleted in the inverse are only reported as `CrateDeleted` if they were added
+/// with `CrateCreated`, as otherwise it's unknown if they were the only ones in their crate.
+pub fn invert(changes: impl IntoIterator<Item = Change>) -> Vec<Change> {
+    let mut out = Vec::new();
+    let mut squashed = Vec::new();
+    for change in changes {
+        match change {
+            Change::Added { version: v, .. } | Change::AddedAndYanked(v) => {
+                out.push(Change::VersionDeleted(v))
+            }
+            Change::Yanked(v) => out.push(Change::Unyanked(CrateVersion { yanked: false, ..v })),
+            Change::Unyanked(v) => out.push(Change::Yanked(CrateVersion { yanked: true, ..v })),
+            Change::VersionDeleted(v) => out.push(added(v)),
+            Change::CrateCreated { name, versions } => {
+                out.push(Change::CrateDeleted { name, versions })
+            }
+            Change::CrateDeleted { versions, .. } => {
+                out.extend(versions.into_iter().rev().map(added))
+            }
+            Change::Squashed { from, to } => squashed.push(Change::Squashed { from: to, to: from }),
+        }
+    }
+    squashed.extend(out.into_iter().rev());
+    squashed
+}
+
+fn added(v: CrateVersion) -> Change {
+    if v.yanked {
+        Change::AddedAndYanked(v)
+    } else {
+        Change::Added {
+            version: v,
+            dependency_changes: None,
+            feature_changes: None,
+        }
+    }
+}
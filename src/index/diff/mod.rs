@@ -1,13 +1,30 @@
 use crate::{Change, Index};
 use bstr::ByteSlice;
+use gix::lock::acquire::Fail;
 use gix::prelude::ObjectIdExt;
+use gix::protocol::transport::IsSpuriousError;
+use gix::remote::fetch::Shallow;
 use gix::traverse::commit::simple::CommitTimeOrder;
 use std::sync::atomic::AtomicBool;
 
-mod delegate;
+#[cfg(feature = "async")]
+mod asynchronous;
+mod coalesce;
+pub(crate) mod delegate;
 mod github;
+mod invert;
 
+pub use coalesce::coalesce;
 use delegate::Delegate;
+pub use invert::invert;
+
+/// The name of the file in the git directory to lock while fetching.
+static LOCK_NAME: &str = "crates-index-diff.lock";
+
+/// An advisory lock on [`LOCK_NAME`], held until it's dropped.
+pub(crate) struct Lock {
+    _file: std::fs::File,
+}
 
 /// The order we maintain for the produced changes.
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
@@ -27,6 +44,17 @@ pub enum Order {
     AsInCratesIndex,
 }
 
+/// The commits that were fetched but not seen yet, as returned by [`Index::pending_commits()`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PendingCommits {
+    /// The commit the remote tracking branch points to.
+    pub tip: gix::hash::ObjectId,
+    /// The number of commits reachable from `tip` which aren't reachable from the last-seen commit.
+    pub count: usize,
+    /// The commit times of the oldest and the newest pending commit, or `None` if there are no pending commits.
+    pub time_span: Option<std::ops::RangeInclusive<std::time::SystemTime>>,
+}
+
 /// The error returned by methods dealing with obtaining index changes.
 #[derive(Debug, thiserror::Error)]
 #[allow(missing_docs)]
@@ -56,6 +84,15 @@ pub enum Error {
     #[error(transparent)]
     FindReference(#[from] Box<gix::reference::find::existing::Error>),
     #[error(transparent)]
+    TryFindReference(#[from] Box<gix::reference::find::Error>),
+    #[error(
+        "The last seen commit {id} of reference {name:?} is missing, and its tree wasn't preserved either"
+    )]
+    LastSeenObjectMissing {
+        name: String,
+        id: gix::hash::ObjectId,
+    },
+    #[error(transparent)]
     Connect(#[from] Box<gix::remote::connect::Error>),
     #[error(transparent)]
     PrepareFetch(#[from] Box<gix::remote::fetch::prepare::Error>),
@@ -69,22 +106,85 @@ pub enum Error {
         name: String,
         mappings: Vec<gix::remote::fetch::refmap::Mapping>,
     },
+    #[error("Couldn't determine if the last-seen commit is an ancestor of the fetched commit")]
+    MergeBase(#[from] Box<gix::repository::merge_base::Error>),
+    #[error("The branch {name:?} doesn't exist on the remote{}", remote_head.as_ref().map(|head| format!(", its default branch is {head:?}")).unwrap_or_default())]
+    BranchNotOnRemote {
+        name: String,
+        remote_head: Option<String>,
+    },
+    #[error("There is no remote tracking branch for branch {name:?}, it must be fetched first")]
+    NoTrackingBranch { name: String },
+    #[error("Couldn't traverse the commits that weren't seen yet")]
+    RevWalk(#[from] Box<gix::revision::walk::Error>),
+    #[error(transparent)]
+    RevWalkIter(#[from] Box<gix::revision::walk::iter::Error>),
+    #[error("Couldn't obtain the lock at {path:?} to fetch changes as another process holds it")]
+    Lock { path: std::path::PathBuf },
+    #[error("Couldn't open or lock the lock file at {path:?}")]
+    LockFile {
+        source: std::io::Error,
+        path: std::path::PathBuf,
+    },
+    #[error("Expected {spec:?} to be a single revision")]
+    NotASingleRevision { spec: String },
+    #[error("Expected {spec:?} to be a range like `A..B`, `A...B` or `A^!`")]
+    NotARange { spec: String },
+    #[error("The first-parent ancestry of commit {expected} unexpectedly ended in commit {actual}")]
+    AncestryTipMismatch {
+        expected: gix::hash::ObjectId,
+        actual: gix::hash::ObjectId,
+    },
+    #[error(
+        "No commit before the start of the time range is reachable, as the history ends in commit {oldest} whose parents are missing"
+    )]
+    StartOfTimeRangeMissing { oldest: gix::hash::ObjectId },
+    #[error("Couldn't decode a commit to learn its commit time")]
+    DecodeCommit(#[from] Box<gix::objs::decode::Error>),
+    #[error("The default branch of the remote can't be remembered as its name is invalid")]
+    RemoteHeadName(#[from] Box<gix::validate::reference::name::Error>),
+    #[error("The refspec to fetch the branch is invalid")]
+    RefSpec(#[from] Box<gix::refspec::parse::Error>),
     #[error("Error when fetching GitHub fastpath.")]
     GithubFetch(#[from] reqwest::Error),
 }
 
+impl Error {
+    /// Return `true` if the error was caused by a network failure that might not occur again, like a connection
+    /// that was reset or timed out, so retrying the operation might succeed.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Error::Connect(err) => err.is_spurious(),
+            Error::PrepareFetch(err) => err.is_spurious(),
+            Error::Fetch(err) => err.is_spurious(),
+            Error::GithubFetch(err) => {
+                err.is_connect()
+                    || err.is_timeout()
+                    || err.status().is_some_and(|status| status.is_server_error())
+            }
+            _ => false,
+        }
+    }
+}
+
 impl_from_boxed!(gix::diff::new_rewrites::Error => Error::DiffRewrites);
 impl_from_boxed!(gix::diff::options::init::Error => Error::Diff);
 impl_from_boxed!(gix::object::find::existing::Error => Error::FindObject);
+impl_from_boxed!(gix::objs::decode::Error => Error::DecodeCommit);
 impl_from_boxed!(gix::object::peel::to_kind::Error => Error::PeelToTree);
 impl_from_boxed!(gix::object::tree::diff::for_each::Error => Error::DiffForEach);
 impl_from_boxed!(gix::reference::edit::Error => Error::ReferenceEdit);
+impl_from_boxed!(gix::refspec::parse::Error => Error::RefSpec);
 impl_from_boxed!(gix::reference::find::existing::Error => Error::FindReference);
+impl_from_boxed!(gix::reference::find::Error => Error::TryFindReference);
 impl_from_boxed!(gix::remote::connect::Error => Error::Connect);
 impl_from_boxed!(gix::remote::fetch::Error => Error::Fetch);
 impl_from_boxed!(gix::remote::fetch::prepare::Error => Error::PrepareFetch);
 impl_from_boxed!(gix::remote::find::existing::Error => Error::FindRemote);
 impl_from_boxed!(gix::remote::init::Error => Error::InitAnonymousRemote);
+impl_from_boxed!(gix::repository::merge_base::Error => Error::MergeBase);
+impl_from_boxed!(gix::revision::walk::Error => Error::RevWalk);
+impl_from_boxed!(gix::revision::walk::iter::Error => Error::RevWalkIter);
 impl_from_boxed!(gix::Error => Error::RevParse);
 
 /// Find changes without modifying the underling repository
@@ -116,6 +216,12 @@ impl Index {
     /// If one would set the [`Self::last_seen_reference()`] to that object, the effect is exactly the same
     /// as if [`Self::fetch_changes()`] had been called.
     ///
+    /// If the last seen commit isn't an ancestor of the fetched commit anymore, the upstream history was squashed.
+    /// This is signalled with a leading [`Change::Squashed`], followed by the net changes between both commits.
+    ///
+    /// If [`Config::offline`](crate::index::Config::offline) is set, the network isn't accessed and changes are obtained up to the local remote tracking branch
+    /// instead, which fails with [`Error::NoTrackingBranch`] if there is none.
+    ///
     /// The `progress` and `should_interrupt` parameters are used to provide progress for fetches and allow
     /// these operations to be interrupted gracefully.
     ///
@@ -126,8 +232,28 @@ impl Index {
     /// of the maximum number of open file handles as configured with `ulimit`.
     ///
     /// Thus it is advised for the caller to run `git gc` occasionally based on their own requirements and usage patterns.
+    ///
+    /// # Locking
+    ///
+    /// The fetch and diff happen while holding a lock shared with other processes using the same repository,
+    /// waiting for at most [`Config::lock_timeout`](crate::index::Config::lock_timeout) to obtain it.
     // TODO: update this once it's clear how auto-gc works in `gitoxide`.
     pub fn peek_changes_with_options<P>(
+        &self,
+        progress: P,
+        should_interrupt: &AtomicBool,
+        order: Order,
+    ) -> Result<(Vec<Change>, gix::hash::ObjectId), Error>
+    where
+        P: gix::NestedProgress,
+        P::SubProgress: 'static,
+    {
+        let _lock = self.lock(Fail::AfterDurationWithBackoff(self.config.lock_timeout))?;
+        self.peek_changes_locked(progress, should_interrupt, order)
+    }
+
+    /// Like [`Self::peek_changes_with_options()`], but assumes the caller holds the lock.
+    fn peek_changes_locked<P>(
         &self,
         mut progress: P,
         should_interrupt: &AtomicBool,
@@ -137,105 +263,441 @@ impl Index {
         P: gix::NestedProgress,
         P::SubProgress: 'static,
     {
-        let repo = &self.repo;
-        let from = repo
-            .find_reference(self.seen_ref_name)
-            .ok()
-            .and_then(|r| r.try_id().map(|id| id.detach()))
-            .unwrap_or_else(|| gix::hash::ObjectId::empty_tree(repo.object_hash()));
-        let to = {
-            let mut remote = self
-                .remote_name
-                .as_deref()
-                .and_then(|name| {
-                    self.repo.find_remote(name.as_bstr()).ok().or_else(|| {
-                        self.repo
-                            .head()
-                            .ok()
-                            .and_then(|head| {
-                                head.into_remote(gix::remote::Direction::Fetch)
-                                    .and_then(|r| r.ok())
-                            })
-                            .or_else(|| {
-                                self.repo
-                                    .find_default_remote(gix::remote::Direction::Fetch)
-                                    .and_then(|r| r.ok())
-                            })
-                    })
-                })
-                .map(Ok)
-                .unwrap_or_else(|| {
-                    self.repo
-                        .head()?
-                        .into_remote(gix::remote::Direction::Fetch)
-                        .map(|r| r.map_err(Error::from))
-                        .or_else(|| {
-                            self.repo
-                                .find_default_remote(gix::remote::Direction::Fetch)
-                                .map(|r| r.map_err(Error::from))
-                        })
-                        .unwrap_or_else(|| {
-                            self.repo
-                                .remote_at("https://github.com/rust-lang/crates.io-index")
-                                .map_err(Into::into)
-                        })
-                })?;
-            if remote.refspecs(gix::remote::Direction::Fetch).is_empty() {
-                let spec = format!(
-                    "+refs/heads/{branch}:refs/remotes/{remote}/{branch}",
-                    remote = self
-                        .remote_name
-                        .as_ref()
-                        .map(|n| n.as_bstr())
-                        .unwrap_or("origin".into()),
-                    branch = self.branch_name,
-                );
-                remote
-                    .replace_refspecs(Some(spec.as_str()), gix::remote::Direction::Fetch)
-                    .expect("valid statically known refspec");
+        let (last_seen, from) = self.last_seen_and_from()?;
+        let to = self.fetch_tip(&mut progress, should_interrupt, from)?;
+
+        let mut changes = Vec::new();
+        match last_seen {
+            Some(last_seen) if last_seen != from => changes.push(Change::Squashed {
+                from: last_seen,
+                to,
+            }),
+            _ if self.is_squashed(from, to, &mut progress, should_interrupt)? => {
+                changes.push(Change::Squashed { from, to })
             }
+            _ => {}
+        }
+        changes.extend(match order {
+            Order::ImplementationDefined => self.changes_between_commits(from, to)?,
+            Order::AsInCratesIndex => {
+                self.changes_between_ancestor_commits_with_options(
+                    from,
+                    to,
+                    &mut progress,
+                    should_interrupt,
+                )?
+                .0
+            }
+        });
+        Ok((changes, to))
+    }
 
-            let (url, _) = remote.sanitized_url_and_version(gix::remote::Direction::Fetch)?;
-            if matches!(
-                github::has_changes(&url, &from, self.branch_name)?,
+    /// Fetch the branch to obtain changes from and return the commit its remote tracking branch points to,
+    /// or return `from` if the GitHub API indicates that there are no changes.
+    fn fetch_tip<P>(
+        &self,
+        mut progress: P,
+        should_interrupt: &AtomicBool,
+        from: gix::hash::ObjectId,
+    ) -> Result<gix::hash::ObjectId, Error>
+    where
+        P: gix::NestedProgress,
+        P::SubProgress: 'static,
+    {
+        if self.config.offline {
+            return Ok(self.tracking_branch()?.id().detach());
+        }
+        let (url, _) = self
+            .remote()?
+            .sanitized_url_and_version(gix::remote::Direction::Fetch)?;
+        if self.config.github_fast_path
+            && matches!(
+                self.config
+                    .retry_policy
+                    .retry(should_interrupt, Error::is_transient, || Ok(
+                        github::has_changes(&url, &from, self.github_branch())?
+                    ))?,
                 github::FastPath::UpToDate
-            ) {
-                from
-            } else {
-                let res: gix::remote::fetch::Outcome = remote
-                    .connect(gix::remote::Direction::Fetch)?
-                    .prepare_fetch(&mut progress, Default::default())?
-                    .receive(&mut progress, should_interrupt)?;
-                let branch_name = format!("refs/heads/{}", self.branch_name);
-                let local_tracking = res
+            )
+        {
+            return Ok(from);
+        }
+
+        // Also list the remote `HEAD` to learn about its default branch.
+        let options = gix::remote::ref_map::Options {
+            extra_refspecs: vec![
+                gix::refspec::parse("HEAD".into(), gix::refspec::parse::Operation::Fetch)
+                    .expect("valid statically known refspec")
+                    .to_owned(),
+            ],
+            ..Default::default()
+        };
+        let mut branch = self.branch_name().into_owned();
+        let mut refetched = false;
+        loop {
+            let remote = self.remote_for_branch(&branch)?;
+            let res: gix::remote::fetch::Outcome =
+                self.config
+                    .retry_policy
+                    .retry(should_interrupt, Error::is_transient, || {
+                        Ok(remote
+                            .connect(gix::remote::Direction::Fetch)?
+                            .prepare_fetch(&mut progress, options.clone())?
+                            .receive(&mut progress, should_interrupt)?)
+                    })?;
+            let local_tracking = |branch_name: &str| {
+                res.ref_map.mappings.iter().find_map(|m| match &m.remote {
+                    gix::remote::fetch::refmap::Source::Ref(r) => (r.unpack().0 == branch_name)
+                        .then_some(m.local.as_ref())
+                        .flatten(),
+                    _ => None,
+                })
+            };
+            let remote_head = self
+                .config
+                .follow_remote_head
+                .then(|| remote_head_branch(&res.ref_map))
+                .flatten();
+            if let Some(head) = remote_head.filter(|head| *head != branch) {
+                // The default branch of the remote changed, remember it for all operations that use the tracking branch.
+                self.set_remote_head(&head)?;
+                let fetched = local_tracking(&format!("refs/heads/{head}")).is_some();
+                branch = head;
+                if !fetched && !refetched {
+                    refetched = true;
+                    continue;
+                }
+            }
+            let branch_name = format!("refs/heads/{branch}");
+            let local_tracking = local_tracking(&branch_name).ok_or_else(|| {
+                if res
                     .ref_map
-                    .mappings
+                    .remote_refs
                     .iter()
-                    .find_map(|m| match &m.remote {
-                        gix::remote::fetch::refmap::Source::Ref(r) => (r.unpack().0 == branch_name)
-                            .then_some(m.local.as_ref())
-                            .flatten(),
-                        _ => None,
-                    })
-                    .ok_or_else(|| Error::NoMatchingBranch {
-                        name: branch_name,
+                    .any(|r| r.unpack().0 == branch_name)
+                {
+                    Error::NoMatchingBranch {
+                        name: branch_name.clone(),
                         mappings: res.ref_map.mappings.clone(),
-                    })?;
+                    }
+                } else {
+                    Error::BranchNotOnRemote {
+                        name: branch_name.clone(),
+                        remote_head: remote_head_branch(&res.ref_map),
+                    }
+                }
+            })?;
+            return Ok(self.repo.find_reference(local_tracking)?.id().detach());
+        }
+    }
+
+    /// Return the branch to obtain changes from.
+    ///
+    /// If [`Config::follow_remote_head`](crate::index::Config::follow_remote_head) is set, this is the default branch of
+    /// the remote as seen by the last fetch, and [`Config::branch_name`](crate::index::Config::branch_name) otherwise.
+    pub fn branch_name(&self) -> std::borrow::Cow<'_, str> {
+        self.config
+            .follow_remote_head
+            .then_some(self.config.remote_name.as_ref())
+            .flatten()
+            .and_then(|remote| {
+                crate::index::builder::remote_head_branch_name(&self.repo, remote.as_bstr())
+            })
+            .map(std::borrow::Cow::Owned)
+            .unwrap_or(std::borrow::Cow::Borrowed(&self.config.branch_name))
+    }
+
+    /// Point `refs/remotes/<remote>/HEAD` to the remote tracking branch of `branch`, the new default branch of the remote,
+    /// so [`Self::branch_name()`] returns it.
+    ///
+    /// Nothing is remembered if the remote has no name.
+    fn set_remote_head(&self, branch: &str) -> Result<(), Error> {
+        use gix::refs::transaction::{Change, LogChange, PreviousValue, RefEdit, RefLog};
+        let Some(remote) = &self.config.remote_name else {
+            return Ok(());
+        };
+        let name = |name: String| gix::refs::FullName::try_from(name).map_err(Box::new);
+        self.repo.edit_reference(RefEdit {
+            change: Change::Update {
+                log: LogChange {
+                    mode: RefLog::AndReference,
+                    force_create_reflog: false,
+                    message: "remote HEAD changed".into(),
+                },
+                expected: PreviousValue::Any,
+                new: gix::refs::Target::Symbolic(name(format!("refs/remotes/{remote}/{branch}"))?),
+            },
+            name: name(format!("refs/remotes/{remote}/HEAD"))?,
+            deref: false,
+        })?;
+        Ok(())
+    }
+
+    /// Return the remote to fetch from, with refspecs for the branch to fetch and possibly the `snapshot-*` branches.
+    pub(crate) fn remote(&self) -> Result<gix::Remote<'_>, Error> {
+        self.remote_for_branch(&self.branch_name())
+    }
+
+    /// Like [`Self::remote()`], but fetch `branch` if the remote has no fetch refspecs.
+    fn remote_for_branch(&self, branch: &str) -> Result<gix::Remote<'_>, Error> {
+        let mut remote = self
+            .config
+            .remote_name
+            .as_deref()
+            .and_then(|name| {
+                self.repo.find_remote(name.as_bstr()).ok().or_else(|| {
+                    self.repo
+                        .head()
+                        .ok()
+                        .and_then(|head| {
+                            head.into_remote(gix::remote::Direction::Fetch)
+                                .and_then(|r| r.ok())
+                        })
+                        .or_else(|| {
+                            self.repo
+                                .find_default_remote(gix::remote::Direction::Fetch)
+                                .and_then(|r| r.ok())
+                        })
+                })
+            })
+            .map(Ok)
+            .unwrap_or_else(|| {
                 self.repo
-                    .find_reference(local_tracking)
-                    .expect("local tracking branch exists if we see it here")
-                    .id()
-                    .detach()
+                    .head()?
+                    .into_remote(gix::remote::Direction::Fetch)
+                    .map(|r| r.map_err(Error::from))
+                    .or_else(|| {
+                        self.repo
+                            .find_default_remote(gix::remote::Direction::Fetch)
+                            .map(|r| r.map_err(Error::from))
+                    })
+                    .unwrap_or_else(|| {
+                        self.repo
+                            .remote_at("https://github.com/rust-lang/crates.io-index")
+                            .map_err(Into::into)
+                    })
+            })?;
+        let remote_name = self
+            .config
+            .remote_name
+            .as_ref()
+            .map(|n| n.as_bstr())
+            .unwrap_or("origin".into());
+        if remote.refspecs(gix::remote::Direction::Fetch).is_empty() {
+            let spec = format!("+refs/heads/{branch}:refs/remotes/{remote_name}/{branch}");
+            remote.replace_refspecs(Some(spec.as_str()), gix::remote::Direction::Fetch)?;
+        }
+        if self.config.fetch_snapshot_branches
+            && !remote
+                .refspecs(gix::remote::Direction::Fetch)
+                .iter()
+                .any(|spec| {
+                    matches!(
+                        spec.to_ref().source().map(|s| s.as_bytes()),
+                        Some(b"refs/heads/*" | b"refs/heads/snapshot-*")
+                    )
+                })
+        {
+            let mut specs: Vec<_> = remote
+                .refspecs(gix::remote::Direction::Fetch)
+                .iter()
+                .map(|spec| spec.to_ref().to_bstring())
+                .collect();
+            specs.push(
+                format!("+refs/heads/snapshot-*:refs/remotes/{remote_name}/snapshot-*").into(),
+            );
+            remote.replace_refspecs(specs, gix::remote::Direction::Fetch)?;
+        }
+        Ok(remote)
+    }
+
+    /// Return how far the last-seen reference is behind the remote tracking branch of [`Config::branch_name`](crate::index::Config::branch_name),
+    /// without fetching or diffing.
+    ///
+    /// Use [`Self::fetch_only()`] to update the remote tracking branch beforehand to learn how much there is to catch up on.
+    pub fn pending_commits(&self) -> Result<PendingCommits, Error> {
+        let tip = self.tracking_branch()?.id().detach();
+        let last_seen = self
+            .last_seen_and_from()?
+            .0
+            .filter(|id| self.repo.has_object(id));
+        let mut count = 0;
+        let mut time_span = None::<(
+            gix::date::SecondsSinceUnixEpoch,
+            gix::date::SecondsSinceUnixEpoch,
+        )>;
+        for info in tip
+            .attach(&self.repo)
+            .ancestors()
+            .with_hidden(last_seen)
+            .sorting(gix::revision::walk::Sorting::ByCommitTime(
+                CommitTimeOrder::NewestFirst,
+            ))
+            .all()?
+        {
+            let info = info?;
+            count += 1;
+            if let Some(time) = info.commit_time {
+                time_span = Some(match time_span {
+                    Some((oldest, newest)) => (oldest.min(time), newest.max(time)),
+                    None => (time, time),
+                });
             }
+        }
+        Ok(PendingCommits {
+            tip,
+            count,
+            time_span: time_span.map(|(oldest, newest)| system_time(oldest)..=system_time(newest)),
+        })
+    }
+
+    /// Return the remote tracking branch that [`Self::branch_name()`] is fetched into.
+    pub(crate) fn tracking_branch(&self) -> Result<gix::Reference<'_>, Error> {
+        let branch = format!("refs/heads/{}", self.branch_name());
+        self.tracking_branch_name()?
+            .and_then(|name| self.repo.try_find_reference(name.as_bstr()).transpose())
+            .transpose()?
+            .ok_or(Error::NoTrackingBranch { name: branch })
+    }
+
+    /// Return the name of the remote tracking branch that [`Self::branch_name()`] is fetched into,
+    /// according to the fetch refspecs of the remote, or `None` if it isn't fetched at all.
+    pub(crate) fn tracking_branch_name(&self) -> Result<Option<bstr::BString>, Error> {
+        let branch = self.branch_name().into_owned();
+        let remote = self.remote_for_branch(&branch)?;
+        let branch = format!("refs/heads/{branch}");
+        let null = gix::hash::ObjectId::null(self.repo.object_hash());
+        Ok(gix::refspec::MatchGroup::from_fetch_specs(
+            remote
+                .refspecs(gix::remote::Direction::Fetch)
+                .iter()
+                .map(|spec| spec.to_ref()),
+        )
+        .match_lhs(std::iter::once(gix::refspec::match_group::Item {
+            full_ref_name: branch.as_str().into(),
+            target: &null,
+            object: None,
+        }))
+        .mappings
+        .into_iter()
+        .find_map(|m| m.rhs.map(|name| name.into_owned())))
+    }
+
+    /// Return the last seen commit, if there is one, along with the commit or tree to obtain changes from.
+    pub(crate) fn last_seen_and_from(
+        &self,
+    ) -> Result<(Option<gix::hash::ObjectId>, gix::hash::ObjectId), Error> {
+        let repo = &self.repo;
+        let last_seen = repo
+            .try_find_reference(self.config.seen_ref_name.as_str())?
+            .and_then(|r| r.try_id().map(|id| id.detach()));
+        let from = match last_seen {
+            Some(id) if repo.has_object(id) => id,
+            Some(id) => self.last_seen_tree(id)?,
+            None => gix::hash::ObjectId::empty_tree(repo.object_hash()),
         };
+        Ok((last_seen, from))
+    }
 
-        Ok((
-            match order {
-                Order::ImplementationDefined => self.changes_between_commits(from, to)?,
-                Order::AsInCratesIndex => self.changes_between_ancestor_commits(from, to)?.0,
-            },
-            to,
-        ))
+    /// The revision to ask the GitHub API about when checking for changes.
+    fn github_branch(&self) -> &str {
+        if self.config.follow_remote_head {
+            "HEAD"
+        } else {
+            &self.config.branch_name
+        }
+    }
+
+    /// Return the tree preserved alongside the last seen commit `id`, which is used if the commit itself
+    /// was garbage-collected after the upstream history was squashed.
+    fn last_seen_tree(&self, id: gix::hash::ObjectId) -> Result<gix::hash::ObjectId, Error> {
+        self.repo
+            .try_find_reference(self.seen_tree_ref_name().as_str())?
+            .and_then(|r| r.try_id().map(|id| id.detach()))
+            .filter(|tree| self.repo.has_object(tree))
+            .ok_or_else(|| Error::LastSeenObjectMissing {
+                name: self.config.seen_ref_name.clone(),
+                id,
+            })
+    }
+
+    /// Return `true` if `from` is a commit that isn't an ancestor of `to`, which happens if the upstream
+    /// history was squashed.
+    fn is_squashed<P>(
+        &self,
+        from: gix::hash::ObjectId,
+        to: gix::hash::ObjectId,
+        progress: &mut P,
+        should_interrupt: &AtomicBool,
+    ) -> Result<bool, Error>
+    where
+        P: gix::NestedProgress,
+        P::SubProgress: 'static,
+    {
+        if from == to || self.repo.find_header(from)?.kind() != gix::object::Kind::Commit {
+            return Ok(false);
+        }
+        let mut deepen = self.deepen_steps(from);
+        loop {
+            match self.repo.merge_base(from, to) {
+                Ok(base) => return Ok(base != from),
+                Err(gix::repository::merge_base::Error::NotFound { .. }) => {
+                    if !self.deepen(&mut deepen, progress, should_interrupt)? {
+                        return Ok(true);
+                    }
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+
+    /// Return the ways to deepen a shallow repository so that `commit` becomes reachable, from cheapest to most expensive.
+    fn deepen_steps(&self, commit: gix::hash::ObjectId) -> std::vec::IntoIter<Shallow> {
+        let since_commit = commit
+            .attach(&self.repo)
+            .object()
+            .ok()
+            .and_then(|obj| obj.try_into_commit().ok())
+            .and_then(|commit| commit.time().ok())
+            .map(|cutoff| Shallow::Since { cutoff });
+        since_commit
+            .into_iter()
+            .chain(Some(Shallow::undo()))
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    /// If the repository is shallow, fetch more history according to the next of the `steps` and return `true`,
+    /// or return `false` if there is nothing more we can do.
+    ///
+    /// The `progress` and `should_interrupt` parameters are used for the fetch, which may receive the entire history.
+    fn deepen<P>(
+        &self,
+        steps: &mut impl Iterator<Item = Shallow>,
+        progress: &mut P,
+        should_interrupt: &AtomicBool,
+    ) -> Result<bool, Error>
+    where
+        P: gix::NestedProgress,
+        P::SubProgress: 'static,
+    {
+        if self.config.offline || !self.repo.is_shallow() {
+            return Ok(false);
+        }
+        let Some(shallow) = steps.next() else {
+            return Ok(false);
+        };
+        let remote = self.remote()?;
+        self.config
+            .retry_policy
+            .retry(should_interrupt, Error::is_transient, || {
+                Ok(remote
+                    .connect(gix::remote::Direction::Fetch)?
+                    .prepare_fetch(&mut *progress, Default::default())?
+                    .with_shallow(shallow.clone())
+                    .receive(&mut *progress, should_interrupt)?)
+            })?;
+        Ok(true)
     }
 
     /// Similar to [`Self::changes()`], but requires `from` and `to` objects to be provided. They may point
@@ -267,7 +729,7 @@ impl Index {
         };
         let from = into_tree(from.into())?;
         let to = into_tree(to.into())?;
-        let mut delegate = Delegate::default();
+        let mut delegate = Delegate::new(self);
         from.changes()?
             .options(|opts| {
                 opts.track_rewrites(None).track_filename();
@@ -279,9 +741,10 @@ impl Index {
     /// Similar to [`Self::changes()`], but requires `ancestor_commit` and `current_commit` objects to be provided
     /// with `ancestor_commit` being in the ancestry of `current_commit`.
     ///
-    /// If the invariants regarding `ancestor_commit` and `current_commit` are not upheld, we fallback
+    /// If the crates-index was squashed, the history is connected through `snapshot-*` branches if these are
+    /// available, see [`Config::fetch_snapshot_branches`](crate::index::Config::fetch_snapshot_branches).
+    /// If the invariants regarding `ancestor_commit` and `current_commit` are still not upheld, we fallback
     /// to `changes_between_commits()` which doesn't have such restrictions.
-    /// This can happen if the crates-index was squashed for instance.
     ///
     /// # Returns
     ///
@@ -301,9 +764,30 @@ impl Index {
         ancestor_commit: impl Into<gix::hash::ObjectId>,
         current_commit: impl Into<gix::hash::ObjectId>,
     ) -> Result<(Vec<Change>, Order), Error> {
+        self.changes_between_ancestor_commits_with_options(
+            ancestor_commit,
+            current_commit,
+            gix::progress::Discard,
+            &AtomicBool::default(),
+        )
+    }
+
+    /// Like [`Self::changes_between_ancestor_commits()`], but the `progress` and `should_interrupt` parameters are used
+    /// to provide progress for fetches that deepen a shallow repository, and allow these to be interrupted gracefully.
+    pub fn changes_between_ancestor_commits_with_options<P>(
+        &self,
+        ancestor_commit: impl Into<gix::hash::ObjectId>,
+        current_commit: impl Into<gix::hash::ObjectId>,
+        mut progress: P,
+        should_interrupt: &AtomicBool,
+    ) -> Result<(Vec<Change>, Order), Error>
+    where
+        P: gix::NestedProgress,
+        P::SubProgress: 'static,
+    {
         let from_commit = ancestor_commit.into();
         let to_commit = current_commit.into();
-        match self.commit_ancestry(from_commit, to_commit) {
+        match self.commit_ancestry(from_commit, to_commit, &mut progress, should_interrupt)? {
             Some(commits) => {
                 let mut changes = Vec::new();
                 for from_to in commits.windows(2) {
@@ -319,8 +803,229 @@ impl Index {
         }
     }
 
+    /// As `changes_between_times()`, but returns all changes made at or after `start`.
+    pub fn changes_since(&self, start: std::time::SystemTime) -> Result<Vec<Change>, Error> {
+        self.changes_between_seconds(
+            seconds(start),
+            gix::date::SecondsSinceUnixEpoch::MAX,
+            gix::progress::Discard,
+            &AtomicBool::default(),
+        )
+    }
+
+    /// Return all changes made by commits of the remote tracking branch of [`Config::branch_name`](crate::index::Config::branch_name) whose commit time
+    /// is at or after `start`, and before `end`, without fetching.
+    ///
+    /// Commits are resolved by walking the first-parent ancestry of the remote tracking branch, assuming that commit
+    /// times are increasing along it.
+    /// If the repository is shallow, it's deepened until a commit before `start` is reachable.
+    /// If that isn't possible, for instance because [`Config::offline`](crate::index::Config::offline) is set,
+    /// [`Error::StartOfTimeRangeMissing`] is returned.
+    /// If `start` is before the first commit of the history, the changes of the first commit are those of all crates it contains.
+    ///
+    /// # Grouping and Ordering
+    ///
+    /// The changes are looked at one commit at a time, like [`Self::changes_between_ancestor_commits()`] does.
+    pub fn changes_between_times(
+        &self,
+        start: std::time::SystemTime,
+        end: std::time::SystemTime,
+    ) -> Result<Vec<Change>, Error> {
+        self.changes_between_times_with_options(
+            start,
+            end,
+            gix::progress::Discard,
+            &AtomicBool::default(),
+        )
+    }
+
+    /// Like [`Self::changes_between_times()`], but the `progress` and `should_interrupt` parameters are used
+    /// to provide progress for fetches that deepen a shallow repository, and allow these to be interrupted gracefully.
+    pub fn changes_between_times_with_options<P>(
+        &self,
+        start: std::time::SystemTime,
+        end: std::time::SystemTime,
+        progress: P,
+        should_interrupt: &AtomicBool,
+    ) -> Result<Vec<Change>, Error>
+    where
+        P: gix::NestedProgress,
+        P::SubProgress: 'static,
+    {
+        self.changes_between_seconds(seconds(start), seconds(end), progress, should_interrupt)
+    }
+
+    fn changes_between_seconds<P>(
+        &self,
+        start: gix::date::SecondsSinceUnixEpoch,
+        end: gix::date::SecondsSinceUnixEpoch,
+        mut progress: P,
+        should_interrupt: &AtomicBool,
+    ) -> Result<Vec<Change>, Error>
+    where
+        P: gix::NestedProgress,
+        P::SubProgress: 'static,
+    {
+        let tip = self.tracking_branch()?.id().detach();
+        let mut deepen = vec![
+            Shallow::Since {
+                cutoff: gix::date::Time::new(start.saturating_sub(1), 0),
+            },
+            Shallow::undo(),
+        ]
+        .into_iter();
+        let (from, mut commits) = loop {
+            let mut commits = Vec::new();
+            let mut from = None;
+            let mut oldest = None;
+            for info in tip
+                .attach(&self.repo)
+                .ancestors()
+                .first_parent_only()
+                .all()?
+            {
+                let info = info?;
+                let commit = info.object()?;
+                let time = commit.committer()?.seconds();
+                oldest = Some((info.id, commit.parent_ids().next().is_some()));
+                if time >= end {
+                    continue;
+                }
+                if time < start {
+                    from = Some(info.id);
+                    break;
+                }
+                commits.push(info.id);
+            }
+            if from.is_some() {
+                break (from, commits);
+            }
+            if self.deepen(&mut deepen, &mut progress, should_interrupt)? {
+                continue;
+            }
+            match oldest {
+                // The start of the history was reached, so the first commit added everything.
+                None | Some((_, false)) => break (None, commits),
+                Some((oldest, true)) => return Err(Error::StartOfTimeRangeMissing { oldest }),
+            }
+        };
+        commits
+            .push(from.unwrap_or_else(|| gix::hash::ObjectId::empty_tree(self.repo.object_hash())));
+        commits.reverse();
+
+        let mut changes = Vec::new();
+        for from_to in commits.windows(2) {
+            changes.extend(self.changes_between_commits(from_to[0], from_to[1])?);
+        }
+        Ok(changes)
+    }
+
     /// Return a list of commits like `from_commit..=to_commits`.
-    fn commit_ancestry(
+    ///
+    /// If `ancestor_commit` isn't in the ancestry of `current_commit` as the upstream history was squashed,
+    /// try to stitch the history together using the `snapshot-*` branches which archive the history before each squash.
+    /// If the repository is shallow and `ancestor_commit` is beyond the shallow boundary, the repository is deepened
+    /// until it is reachable.
+    fn commit_ancestry<P>(
+        &self,
+        ancestor_commit: gix::hash::ObjectId,
+        current_commit: gix::hash::ObjectId,
+        progress: &mut P,
+        should_interrupt: &AtomicBool,
+    ) -> Result<Option<Vec<gix::hash::ObjectId>>, Error>
+    where
+        P: gix::NestedProgress,
+        P::SubProgress: 'static,
+    {
+        let mut deepen = self.deepen_steps(ancestor_commit);
+        loop {
+            if let Some(commits) = self.direct_commit_ancestry(ancestor_commit, current_commit)? {
+                return Ok(Some(commits));
+            }
+            if let Some(commits) = self.commit_ancestry_through_snapshot(
+                ancestor_commit,
+                current_commit,
+                progress,
+                should_interrupt,
+            )? {
+                return Ok(Some(commits));
+            }
+            if !self.deepen(&mut deepen, progress, should_interrupt)? {
+                return Ok(None);
+            }
+        }
+    }
+
+    /// Find the snapshot branch whose tip has the same tree as the root commit of `current_commit`, which
+    /// is what the squashed history was created from, and connect `ancestor_commit` to it.
+    fn commit_ancestry_through_snapshot<P>(
+        &self,
+        ancestor_commit: gix::hash::ObjectId,
+        current_commit: gix::hash::ObjectId,
+        progress: &mut P,
+        should_interrupt: &AtomicBool,
+    ) -> Result<Option<Vec<gix::hash::ObjectId>>, Error>
+    where
+        P: gix::NestedProgress,
+        P::SubProgress: 'static,
+    {
+        let snapshot_and_squashed = || {
+            let mut squashed = current_commit
+                .attach(&self.repo)
+                .ancestors()
+                .first_parent_only()
+                .all()
+                .ok()?
+                .map(|c| c.map(|c| c.id))
+                .collect::<Result<Vec<_>, _>>()
+                .ok()?;
+            squashed.reverse();
+            let root_tree = tree_id(squashed.first()?.attach(&self.repo))?;
+
+            let references = self.repo.references().ok()?;
+            let snapshot = references
+                .all()
+                .ok()?
+                .filter_map(Result::ok)
+                .filter(|r| is_snapshot_branch(r.name().as_bstr()))
+                .filter_map(|mut r| r.peel_to_id().ok().map(|id| id.detach()))
+                .find(|tip| {
+                    *tip != current_commit && tree_id(tip.attach(&self.repo)) == Some(root_tree)
+                })?;
+            Some((snapshot, squashed))
+        };
+        let Some((snapshot, squashed)) = snapshot_and_squashed() else {
+            return Ok(None);
+        };
+
+        Ok(self
+            .commit_ancestry(ancestor_commit, snapshot, progress, should_interrupt)?
+            .map(|mut commits| {
+                commits.extend(squashed);
+                commits
+            }))
+    }
+
+    /// Return a list of commits like `from_commit..=to_commits` if `ancestor_commit` is reachable from `current_commit`.
+    fn direct_commit_ancestry(
+        &self,
+        ancestor_commit: gix::hash::ObjectId,
+        current_commit: gix::hash::ObjectId,
+    ) -> Result<Option<Vec<gix::hash::ObjectId>>, Error> {
+        let Some(commits) = self.direct_commit_ancestry_inner(ancestor_commit, current_commit)
+        else {
+            return Ok(None);
+        };
+        match commits.last() {
+            Some(tip) if *tip != current_commit => Err(Error::AncestryTipMismatch {
+                expected: current_commit,
+                actual: *tip,
+            }),
+            _ => Ok(Some(commits)),
+        }
+    }
+
+    fn direct_commit_ancestry_inner(
         &self,
         ancestor_commit: gix::hash::ObjectId,
         current_commit: gix::hash::ObjectId,
@@ -354,15 +1059,69 @@ impl Index {
             let pos = commits.iter().position(|c| *c == ancestor_commit)?;
             commits = commits[pos..].into();
         }
-        assert_eq!(
-            commits[commits.len() - 1],
-            current_commit,
-            "the iterator includes the tips"
-        );
         Some(commits)
     }
 }
 
+/// Return the name of the branch the remote `HEAD` points to, like `main`, if it was advertised.
+fn remote_head_branch(ref_map: &gix::remote::fetch::RefMap) -> Option<String> {
+    ref_map.remote_refs.iter().find_map(|r| match r {
+        gix::protocol::handshake::Ref::Symbolic {
+            full_ref_name,
+            target,
+            ..
+        } if full_ref_name == "HEAD" => target
+            .strip_prefix(b"refs/heads/")
+            .and_then(|name| name.to_str().ok())
+            .map(ToOwned::to_owned),
+        _ => None,
+    })
+}
+
+/// Return `true` if `name` is a local or remote tracking branch which archives the history before a squash,
+/// like `refs/remotes/origin/snapshot-2022-08-31`.
+fn is_snapshot_branch(name: &bstr::BStr) -> bool {
+    let branch = name
+        .strip_prefix(b"refs/heads/")
+        .or_else(|| {
+            name.strip_prefix(b"refs/remotes/")
+                .and_then(|name| name.find_byte(b'/').map(|pos| &name[pos + 1..]))
+        })
+        .unwrap_or_default();
+    branch.starts_with(b"snapshot-")
+}
+
+fn system_time(seconds: gix::date::SecondsSinceUnixEpoch) -> std::time::SystemTime {
+    let offset = std::time::Duration::from_secs(seconds.unsigned_abs());
+    if seconds < 0 {
+        std::time::UNIX_EPOCH - offset
+    } else {
+        std::time::UNIX_EPOCH + offset
+    }
+}
+
+/// Convert `time` into seconds since the epoch, rounding up to the next full second.
+fn seconds(time: std::time::SystemTime) -> gix::date::SecondsSinceUnixEpoch {
+    match time.duration_since(std::time::UNIX_EPOCH) {
+        Ok(duration) => {
+            (duration.as_secs() + u64::from(duration.subsec_nanos() > 0))
+                as gix::date::SecondsSinceUnixEpoch
+        }
+        Err(err) => -(err.duration().as_secs() as gix::date::SecondsSinceUnixEpoch),
+    }
+}
+
+fn tree_id(commit: gix::Id<'_>) -> Option<gix::hash::ObjectId> {
+    commit
+        .object()
+        .ok()?
+        .try_into_commit()
+        .ok()?
+        .tree_id()
+        .ok()
+        .map(|id| id.detach())
+}
+
 /// Find changes while changing the underlying repository in one way or another.
 impl Index {
     /// As `fetch_changes_with_options()`, but without the options.
@@ -401,6 +1160,12 @@ impl Index {
     /// of the maximum number of open file handles as configured with `ulimit`.
     ///
     /// Thus it is advised for the caller to run `git gc` occasionally based on their own requirements and usage patterns.
+    ///
+    /// # Locking
+    ///
+    /// Fetching, diffing and updating the last-seen reference happen while holding a lock shared with other processes
+    /// using the same repository, so that each change is observed only once. It waits for at most [`Config::lock_timeout`](crate::index::Config::lock_timeout)
+    /// to obtain the lock, see [`Self::try_fetch_changes_with_options()`] for a variant that doesn't wait.
     pub fn fetch_changes_with_options<P>(
         &self,
         progress: P,
@@ -411,16 +1176,130 @@ impl Index {
         P: gix::NestedProgress,
         P::SubProgress: 'static,
     {
-        let (changes, to) = self.peek_changes_with_options(progress, should_interrupt, order)?;
-        self.set_last_seen_reference(to)?;
+        let _lock = self.lock(Fail::AfterDurationWithBackoff(self.config.lock_timeout))?;
+        self.fetch_changes_locked(progress, should_interrupt, order)
+    }
+
+    /// Like [`Self::fetch_changes_with_options()`], but return `None` immediately if another process currently
+    /// holds the lock.
+    pub fn try_fetch_changes_with_options<P>(
+        &self,
+        progress: P,
+        should_interrupt: &AtomicBool,
+        order: Order,
+    ) -> Result<Option<Vec<Change>>, Error>
+    where
+        P: gix::NestedProgress,
+        P::SubProgress: 'static,
+    {
+        let _lock = match self.lock(Fail::Immediately) {
+            Ok(lock) => lock,
+            Err(Error::Lock { .. }) => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        self.fetch_changes_locked(progress, should_interrupt, order)
+            .map(Some)
+    }
+
+    fn fetch_changes_locked<P>(
+        &self,
+        progress: P,
+        should_interrupt: &AtomicBool,
+        order: Order,
+    ) -> Result<Vec<Change>, Error>
+    where
+        P: gix::NestedProgress,
+        P::SubProgress: 'static,
+    {
+        let (changes, to) = self.peek_changes_locked(progress, should_interrupt, order)?;
+        self.set_last_seen_reference_locked(to)?;
         Ok(changes)
     }
 
+    /// As `fetch_only_with_options()`, but without the options.
+    pub fn fetch_only(&self) -> Result<gix::hash::ObjectId, Error> {
+        self.fetch_only_with_options(gix::progress::Discard, &AtomicBool::default())
+    }
+
+    /// Fetch the latest state of the `crates.io` index repository like [`Self::fetch_changes_with_options()`] does,
+    /// but without computing changes or updating the [`Self::last_seen_reference()`], and return the fetched commit.
+    ///
+    /// Use [`Self::pending_commits()`] to learn how many commits were fetched but not seen yet.
+    pub fn fetch_only_with_options<P>(
+        &self,
+        progress: P,
+        should_interrupt: &AtomicBool,
+    ) -> Result<gix::hash::ObjectId, Error>
+    where
+        P: gix::NestedProgress,
+        P::SubProgress: 'static,
+    {
+        let _lock = self.lock(Fail::AfterDurationWithBackoff(self.config.lock_timeout))?;
+        let (_last_seen, from) = self.last_seen_and_from()?;
+        self.fetch_tip(progress, should_interrupt, from)
+    }
+
     /// Set the last seen reference to the given Oid. It will be created if it does not yet exists.
+    ///
+    /// The tree of `to` is kept reachable as well, see [`Self::last_seen_tree_reference()`].
+    /// The update happens while holding the same lock as [`Self::fetch_changes_with_options()`].
     pub fn set_last_seen_reference(&self, to: gix::hash::ObjectId) -> Result<(), Error> {
+        let _lock = self.lock(Fail::AfterDurationWithBackoff(self.config.lock_timeout))?;
+        self.set_last_seen_reference_locked(to)
+    }
+
+    /// Obtain the lock that serializes fetches and updates of the last-seen reference across processes.
+    ///
+    /// It's an advisory lock on a file in the git directory which is released when the returned lock is dropped,
+    /// or by the operating system when the process ends, so a lock file left behind by a crashed process
+    /// doesn't block anyone.
+    pub(crate) fn lock(&self, mode: Fail) -> Result<Lock, Error> {
+        let path = self.repo.git_dir().join(LOCK_NAME);
+        let lock_err = |source| Error::LockFile {
+            source,
+            path: path.clone(),
+        };
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)
+            .map_err(lock_err)?;
+        let deadline = match mode {
+            Fail::Immediately => std::time::Instant::now(),
+            Fail::AfterDurationWithBackoff(timeout) => std::time::Instant::now() + timeout,
+        };
+        let mut backoff = std::time::Duration::from_millis(1);
+        loop {
+            match file.try_lock() {
+                Ok(()) => return Ok(Lock { _file: file }),
+                Err(std::fs::TryLockError::WouldBlock) => {}
+                Err(std::fs::TryLockError::Error(err)) => return Err(lock_err(err)),
+            }
+            let remaining = deadline.saturating_duration_since(std::time::Instant::now());
+            if remaining.is_zero() {
+                return Err(Error::Lock { path });
+            }
+            std::thread::sleep(backoff.min(remaining));
+            backoff = (backoff * 2).min(std::time::Duration::from_millis(100));
+        }
+    }
+
+    fn set_last_seen_reference_locked(&self, to: gix::hash::ObjectId) -> Result<(), Error> {
         let repo = self.repository();
+        let tree = to
+            .attach(repo)
+            .object()?
+            .peel_to_kind(gix::object::Kind::Tree)?
+            .id;
         repo.reference(
-            self.seen_ref_name,
+            self.seen_tree_ref_name(),
+            tree,
+            gix::refs::transaction::PreviousValue::Any,
+            "preserving tree of latest fetched commit",
+        )?;
+        repo.reference(
+            self.config.seen_ref_name.as_str(),
             to,
             gix::refs::transaction::PreviousValue::Any,
             "updating seen-ref head to latest fetched commit",
@@ -429,7 +1308,7 @@ impl Index {
     }
 
     /// Return all `CreateVersion`s observed between `from` and `to`. Both parameter are ref-specs
-    /// pointing to either a commit or a tree.
+    /// pointing to either a commit or a tree, use [`Self::changes_in_range()`] to pass a range instead.
     /// Learn more about specifying revisions
     /// in the
     /// [official documentation](https://www.kernel.org/pub/software/scm/git/docs/gitrevisions.html)
@@ -451,17 +1330,49 @@ impl Index {
         from: impl AsRef<str>,
         to: impl AsRef<str>,
     ) -> Result<Vec<Change>, Error> {
-        let repo = self.repository();
-        let from = repo
-            .rev_parse(from.as_ref())?
-            .single()
-            .expect("revspec was not a range")
-            .detach();
-        let to = repo
-            .rev_parse(to.as_ref())?
-            .single()
-            .expect("revspec was not a range")
-            .detach();
+        let from = self.single_rev(from.as_ref())?;
+        let to = self.single_rev(to.as_ref())?;
         self.changes_between_commits(from, to)
     }
+
+    /// Like [`Self::changes()`], but obtains both revisions from the range `spec`, like `A..B` for the changes
+    /// between `A` and `B`, `A...B` for the changes between the merge-base of `A` and `B` and `B`,
+    /// or `A^!` for the changes made by `A`.
+    pub fn changes_in_range(&self, spec: impl AsRef<str>) -> Result<Vec<Change>, Error> {
+        let (from, to) = self.rev_range(spec.as_ref())?;
+        self.changes_between_commits(from, to)
+    }
+
+    /// Parse `spec` as a single revision.
+    fn single_rev(&self, spec: &str) -> Result<gix::hash::ObjectId, Error> {
+        self.repo
+            .rev_parse(spec)?
+            .single()
+            .map(|id| id.detach())
+            .ok_or_else(|| Error::NotASingleRevision { spec: spec.into() })
+    }
+
+    /// Parse `spec` as a range and return the revisions to obtain changes from and to.
+    fn rev_range(&self, spec: &str) -> Result<(gix::hash::ObjectId, gix::hash::ObjectId), Error> {
+        use gix::revision::plumbing::Spec;
+        Ok(match self.repo.rev_parse(spec)?.detach() {
+            Spec::Range { from, to } => (from, to),
+            Spec::Merge { theirs, ours } => (self.repo.merge_base(theirs, ours)?.detach(), ours),
+            Spec::ExcludeParents(id) => {
+                let parent = id
+                    .attach(&self.repo)
+                    .object()?
+                    .try_into_commit()
+                    .ok()
+                    .and_then(|commit| commit.parent_ids().next().map(|id| id.detach()));
+                (
+                    parent.unwrap_or_else(|| {
+                        gix::hash::ObjectId::empty_tree(self.repo.object_hash())
+                    }),
+                    id,
+                )
+            }
+            _ => return Err(Error::NotARange { spec: spec.into() }),
+        })
+    }
 }
@@ -1,6 +1,5 @@
-use crate::index::{CloneOptions, LAST_SEEN_REFNAME};
+use crate::index::{CloneOptions, IndexBuilder};
 use crate::Index;
-use std::borrow::Cow;
 use std::path::Path;
 use std::sync::atomic::AtomicBool;
 
@@ -14,19 +13,60 @@ pub enum Error {
     Fetch(#[from] Box<gix::clone::fetch::Error>),
     #[error(transparent)]
     Open(#[from] Box<gix::open::Error>),
+    #[error("The reference name {name:?} is invalid")]
+    InvalidReferenceName {
+        name: String,
+        source: gix::validate::reference::name::Error,
+    },
+    #[error(
+        "The last-seen reference {name:?} must be a reference in `refs/` that isn't a remote tracking branch or the fetched branch"
+    )]
+    SeenReferenceConflict { name: String },
+    #[error("The existing repository fetches from {actual:?}, but {expected:?} was expected")]
+    RemoteUrlMismatch {
+        expected: String,
+        actual: Option<bstr::BString>,
+    },
+    #[error("Couldn't load the repository configuration to change the remote url")]
+    LoadConfig(#[from] Box<gix::config::file::init::from_paths::Error>),
+    #[error("Couldn't change the remote url in the repository configuration")]
+    SetConfig(#[from] Box<gix::config::file::set_raw_value::Error>),
+    #[error("Couldn't lock the repository configuration to change the remote url")]
+    LockConfig(#[from] Box<gix::lock::acquire::Error>),
+    #[error("The repository at {path:?} was never cloned completely")]
+    IncompleteClone { path: std::path::PathBuf },
+    #[error("Refusing to delete {path:?} to clone anew as it isn't a bare repository")]
+    RefuseToRemove { path: std::path::PathBuf },
+    #[error(transparent)]
+    Connect(#[from] Box<gix::remote::connect::Error>),
+    #[error(transparent)]
+    PrepareFetch(#[from] Box<gix::remote::fetch::prepare::Error>),
+    #[error("Couldn't fetch the objects missing from an incomplete clone")]
+    ResumeFetch(#[from] Box<gix::remote::fetch::Error>),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
 }
 
 impl_from_boxed!(gix::clone::Error => Error::PrepareClone);
 impl_from_boxed!(gix::clone::fetch::Error => Error::Fetch);
 impl_from_boxed!(gix::open::Error => Error::Open);
+impl_from_boxed!(gix::remote::connect::Error => Error::Connect);
+impl_from_boxed!(gix::remote::fetch::prepare::Error => Error::PrepareFetch);
+impl_from_boxed!(gix::remote::fetch::Error => Error::ResumeFetch);
+impl_from_boxed!(gix::config::file::init::from_paths::Error => Error::LoadConfig);
+impl_from_boxed!(gix::config::file::set_raw_value::Error => Error::SetConfig);
+impl_from_boxed!(gix::lock::acquire::Error => Error::LockConfig);
 
 /// Initialization
 impl Index {
     /// Return a new `Index` instance from the given `path`, which should contain a bare clone of the `crates.io` index.
     /// If the directory does not contain the repository or does not exist, it will be cloned from
-    /// the official location automatically (with complete history).
+    /// the official location automatically (with complete history, unless [`CloneOptions::shallow`] is set).
+    /// Use [`IndexBuilder`] for more control over the configuration.
     ///
-    /// An error will occour if the repository exists and the remote URL does not match the given repository URL.
+    /// An error will occour if the repository exists and the remote URL does not match the given repository URL,
+    /// unless [`CloneOptions::on_url_mismatch`] is configured otherwise.
+    /// If the repository exists but was never cloned completely, it is handled according to [`CloneOptions::on_incomplete_clone`].
     ///
     /// # Examples
     ///
@@ -38,6 +78,7 @@ impl Index {
     /// // Note that credentials are automatically picked up from the standard git configuration.
     /// let mut options = index::CloneOptions {
     ///   url: "https://github.com/rust-lang/staging.crates.io-index".into(),
+    ///   ..Default::default()
     /// };
     ///
     ///
@@ -48,31 +89,15 @@ impl Index {
         path: impl AsRef<Path>,
         progress: P,
         should_interrupt: &AtomicBool,
-        CloneOptions { url }: CloneOptions,
+        options: CloneOptions,
     ) -> Result<Index, Error>
     where
         P: gix::NestedProgress,
         P::SubProgress: 'static,
     {
-        let path = path.as_ref();
-        let mut repo = match gix::open(path) {
-            Ok(repo) => repo,
-            Err(gix::open::Error::NotARepository { .. }) => {
-                let (repo, _out) =
-                    gix::prepare_clone_bare(url, path)?.fetch_only(progress, should_interrupt)?;
-                repo
-            }
-            Err(err) => return Err(err.into()),
-        };
-
-        repo.object_cache_size_if_unset(4 * 1024 * 1024);
-        let remote_name = repo.remote_names().into_iter().next().map(Cow::into_owned);
-        Ok(Index {
-            repo,
-            remote_name,
-            branch_name: "master",
-            seen_ref_name: LAST_SEEN_REFNAME,
-        })
+        IndexBuilder::new(path.as_ref())
+            .clone_options(options)
+            .build(progress, should_interrupt)
     }
 
     /// Return a new `Index` instance from the given `path`, which should contain a bare or non-bare
@@ -88,3 +113,20 @@ impl Index {
         )
     }
 }
+
+/// Initialization
+#[cfg(feature = "async")]
+impl crate::ThreadSafeIndex {
+    /// Like [`Index::from_path_or_cloned_with_options()`], but clones on the blocking thread pool of the `tokio` runtime.
+    ///
+    /// Dropping the returned future interrupts an ongoing clone.
+    pub async fn from_path_or_cloned_async(
+        path: impl Into<std::path::PathBuf>,
+        options: CloneOptions,
+    ) -> Result<Self, Error> {
+        IndexBuilder::new(path)
+            .clone_options(options)
+            .build_async()
+            .await
+    }
+}
@@ -1,8 +1,12 @@
-use crate::Index;
+use crate::{Index, ThreadSafeIndex};
+use bstr::BString;
 use std::str;
 
 static INDEX_GIT_URL: &str = "https://github.com/rust-lang/crates.io-index";
 static LAST_SEEN_REFNAME: &str = "refs/heads/crates-index-diff_last-seen";
+static LAST_SEEN_TREES_REF_PREFIX: &str = "refs/crates-index-diff/last-seen-trees/";
+/// The name of the file in the git directory that exists while a clone is in progress, and remains if it was interrupted.
+static CLONE_IN_PROGRESS_MARKER: &str = "crates-index-diff-clone-in-progress";
 
 /// Declarative macro to generate `impl From<Src> for Error` where the source
 /// error value is boxed into the given `Error` enum variant.
@@ -26,16 +30,219 @@ macro_rules! impl_from_boxed {
     };
 }
 
+/// The configuration of an [`Index`] for fetching and diffing, which is shared by all thread-local views
+/// of a [`ThreadSafeIndex`].
+///
+/// It's set up with the [`IndexBuilder`] and available through [`Index::config()`].
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// The name and path of the reference used to keep track of the last seen state of the
+    /// crates.io repository. The default value is `refs/heads/crates-index-diff_last-seen`.
+    pub seen_ref_name: String,
+    /// The name of the branch to fetch. This value also affects the tracking branch.
+    ///
+    /// If [`follow_remote_head`](Self::follow_remote_head) is set, this is the default branch detected when opening
+    /// the repository, and [`Index::branch_name()`] returns the branch the remote `HEAD` pointed to when last fetching.
+    pub branch_name: String,
+    /// If `true`, fetch the branch that the remote `HEAD` points to, i.e. its default branch, instead of `branch_name`.
+    pub follow_remote_head: bool,
+    /// The name of the symbolic name of the remote to fetch from.
+    /// If `None`, obtain the remote name from the configuration of the currently checked-out branch.
+    pub remote_name: Option<BString>,
+    /// If `true`, fetch the `snapshot-YYYY-MM-DD` branches that archive the history before each upstream squash,
+    /// which allows to maintain the order of changes across squashes with [`Order::AsInCratesIndex`](diff::Order::AsInCratesIndex).
+    pub fetch_snapshot_branches: bool,
+    /// If `true`, use the GitHub API to learn if there are changes at all before fetching, if the remote is on GitHub.
+    pub github_fast_path: bool,
+    /// The longest time to wait for another process to finish fetching changes from the same repository.
+    pub lock_timeout: std::time::Duration,
+    /// If `true`, never access the network and obtain changes up to the local remote tracking branch of
+    /// [`branch_name`](Self::branch_name) instead of fetching it.
+    pub offline: bool,
+    /// How often to retry fetching if it fails for a reason that might go away,
+    /// as classified by [`Error::is_transient()`](diff::Error::is_transient()).
+    pub retry_policy: RetryPolicy,
+    /// If `true`, report crates whose file appeared as [`Change::CrateCreated`](crate::Change::CrateCreated) instead of an
+    /// [`Added`](crate::Change::Added) or [`AddedAndYanked`](crate::Change::AddedAndYanked) change for each of their versions.
+    pub report_crate_creations: bool,
+    /// If `true`, set the `dependency_changes` of [`Change::Added`](crate::Change::Added) for versions that
+    /// were added to existing crates, comparing them to the highest version below them.
+    ///
+    /// This requires the `semver` feature, and does nothing without it.
+    pub attach_dependency_changes: bool,
+    /// If `true`, set the `feature_changes` of [`Change::Added`](crate::Change::Added) for versions that
+    /// were added to existing crates, comparing them to the highest version below them.
+    ///
+    /// This requires the `semver` feature, and does nothing without it.
+    pub attach_feature_changes: bool,
+    /// The size of the object cache of the repository in bytes, with `0` disabling it.
+    ///
+    /// If `None`, it defaults to 4MB unless configured in the git configuration.
+    /// It's applied when the repository is opened, and to each repository handed out by [`ThreadSafeIndex::to_thread_local()`].
+    pub object_cache_size: Option<usize>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            seen_ref_name: LAST_SEEN_REFNAME.into(),
+            branch_name: "master".into(),
+            follow_remote_head: true,
+            remote_name: None,
+            fetch_snapshot_branches: false,
+            github_fast_path: true,
+            lock_timeout: std::time::Duration::from_secs(5 * 60),
+            offline: false,
+            retry_policy: RetryPolicy::default(),
+            report_crate_creations: false,
+            attach_dependency_changes: false,
+            attach_feature_changes: false,
+            object_cache_size: None,
+        }
+    }
+}
+
+impl Config {
+    /// Set the object cache of `repo` to [`Self::object_cache_size`].
+    pub(crate) fn configure_object_cache(&self, repo: &mut gix::Repository) {
+        match self.object_cache_size {
+            Some(bytes) => repo.object_cache_size(bytes),
+            None => repo.object_cache_size_if_unset(4 * 1024 * 1024),
+        }
+    }
+}
+
 /// Options for cloning the crates-io index.
 pub struct CloneOptions {
     /// The url to clone the crates-index repository from.
     pub url: String,
+    /// What to do if the repository already exists, but its remote doesn't fetch from `url`.
+    pub on_url_mismatch: UrlMismatch,
+    /// How much history to clone, with the default being the complete history.
+    ///
+    /// Use [`Shallow::DepthAtRemote`](gix::remote::fetch::Shallow::DepthAtRemote) to only clone the latest commits,
+    /// or [`Shallow::Since`](gix::remote::fetch::Shallow::Since) to only clone commits after a certain date.
+    /// Shallow clones are deepened automatically if a last-seen commit is beyond the shallow boundary.
+    pub shallow: gix::remote::fetch::Shallow,
+    /// What to do if the repository already exists, but its clone was never completed, for instance because it was
+    /// interrupted or the disk was full.
+    pub on_incomplete_clone: IncompleteClone,
 }
 
 impl Default for CloneOptions {
     fn default() -> Self {
         CloneOptions {
             url: INDEX_GIT_URL.into(),
+            on_url_mismatch: UrlMismatch::default(),
+            shallow: gix::remote::fetch::Shallow::NoChange,
+            on_incomplete_clone: IncompleteClone::default(),
+        }
+    }
+}
+
+/// Determine what happens if an existing repository fetches from a different URL than [`CloneOptions::url`].
+#[derive(Default, Debug, Copy, Clone, Eq, PartialEq)]
+pub enum UrlMismatch {
+    /// Fail with an error.
+    #[default]
+    Error,
+    /// Change the URL of the remote in the repository configuration to [`CloneOptions::url`].
+    RewriteRemoteUrl,
+    /// Delete the directory of the existing repository and clone it from [`CloneOptions::url`].
+    ///
+    /// This fails if the directory isn't a bare repository, as it might contain more than the clone.
+    Reclone,
+}
+
+/// Determine what happens if an existing repository is the result of a clone that didn't complete.
+///
+/// Only clones made by this crate can be detected as incomplete, as a marker is kept in the repository until
+/// all objects were received and references were written. Any other repository is considered complete.
+#[derive(Default, Debug, Copy, Clone, Eq, PartialEq)]
+pub enum IncompleteClone {
+    /// Fetch from the configured remote into the existing repository to receive everything that is missing,
+    /// or clone it anew if there is no remote with fetch refspecs configured yet.
+    #[default]
+    Resume,
+    /// Delete the directory of the existing repository and clone it from [`CloneOptions::url`].
+    ///
+    /// This fails if the directory isn't a bare repository, as it might contain more than the clone.
+    Reclone,
+    /// Fail with an error.
+    Error,
+}
+
+/// Determine how often and how quickly fetches are retried if they fail for reasons that might go away,
+/// like connections that are reset or time out.
+///
+/// The default is to not retry at all.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct RetryPolicy {
+    /// The amount of attempts to make in total, with values smaller than 2 meaning there are no retries.
+    pub max_attempts: usize,
+    /// The time to wait before the first retry, which doubles with each subsequent retry.
+    pub initial_backoff: std::time::Duration,
+    /// The longest time to wait before a retry.
+    pub max_backoff: std::time::Duration,
+    /// The fraction of the backoff, from `0.0` to `1.0`, that is randomly subtracted from it to prevent
+    /// many clients from retrying at the same time.
+    pub jitter: f32,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 1,
+            initial_backoff: std::time::Duration::from_secs(1),
+            max_backoff: std::time::Duration::from_secs(30),
+            jitter: 0.5,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Return the time to wait before the next attempt, after `attempt` attempts have failed.
+    pub fn backoff(&self, attempt: usize) -> std::time::Duration {
+        use std::hash::BuildHasher;
+        let exponent = attempt.saturating_sub(1).min(31) as u32;
+        let backoff = self
+            .initial_backoff
+            .saturating_mul(1 << exponent)
+            .min(self.max_backoff);
+        let random = std::collections::hash_map::RandomState::new().hash_one(attempt) as f64
+            / u64::MAX as f64;
+        backoff.mul_f64(1.0 - f64::from(self.jitter.clamp(0.0, 1.0)) * random)
+    }
+
+    /// Call `f` until it succeeds, fails with an error that `is_transient` doesn't consider worth retrying,
+    /// all attempts are used up or `should_interrupt` is set, waiting according to [`Self::backoff()`] in between.
+    ///
+    /// If `should_interrupt` is set while waiting, the last error is returned right away.
+    pub(crate) fn retry<T, E>(
+        &self,
+        should_interrupt: &std::sync::atomic::AtomicBool,
+        is_transient: impl Fn(&E) -> bool,
+        mut f: impl FnMut() -> Result<T, E>,
+    ) -> Result<T, E> {
+        let interrupted = || should_interrupt.load(std::sync::atomic::Ordering::Relaxed);
+        let mut attempt = 1;
+        loop {
+            match f() {
+                Err(err) if attempt < self.max_attempts && is_transient(&err) && !interrupted() => {
+                    let deadline = std::time::Instant::now() + self.backoff(attempt);
+                    // Sleep in slices to notice interrupts while waiting.
+                    while let Some(remaining) =
+                        deadline.checked_duration_since(std::time::Instant::now())
+                    {
+                        if interrupted() {
+                            return Err(err);
+                        }
+                        std::thread::sleep(remaining.min(std::time::Duration::from_millis(50)));
+                    }
+                    attempt += 1;
+                }
+                res => return res,
+            }
         }
     }
 }
@@ -52,15 +259,159 @@ impl Index {
         &mut self.repo
     }
 
+    /// Return the configuration for fetching and diffing.
+    ///
+    /// Settings that affect references or the remote can only be changed with the [`IndexBuilder`],
+    /// the others have setters.
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Set [`Config::offline`].
+    pub fn set_offline(&mut self, enabled: bool) -> &mut Self {
+        self.config.offline = enabled;
+        self
+    }
+
+    /// Set [`Config::lock_timeout`].
+    pub fn set_lock_timeout(&mut self, timeout: std::time::Duration) -> &mut Self {
+        self.config.lock_timeout = timeout;
+        self
+    }
+
+    /// Set [`Config::retry_policy`].
+    pub fn set_retry_policy(&mut self, policy: RetryPolicy) -> &mut Self {
+        self.config.retry_policy = policy;
+        self
+    }
+
+    /// Set [`Config::github_fast_path`].
+    pub fn set_github_fast_path(&mut self, enabled: bool) -> &mut Self {
+        self.config.github_fast_path = enabled;
+        self
+    }
+
+    /// Set [`Config::fetch_snapshot_branches`].
+    pub fn set_fetch_snapshot_branches(&mut self, enabled: bool) -> &mut Self {
+        self.config.fetch_snapshot_branches = enabled;
+        self
+    }
+
+    /// Set [`Config::report_crate_creations`].
+    pub fn set_report_crate_creations(&mut self, enabled: bool) -> &mut Self {
+        self.config.report_crate_creations = enabled;
+        self
+    }
+
+    /// Set [`Config::attach_dependency_changes`].
+    pub fn set_attach_dependency_changes(&mut self, enabled: bool) -> &mut Self {
+        self.config.attach_dependency_changes = enabled;
+        self
+    }
+
+    /// Set [`Config::attach_feature_changes`].
+    pub fn set_attach_feature_changes(&mut self, enabled: bool) -> &mut Self {
+        self.config.attach_feature_changes = enabled;
+        self
+    }
+
     /// Return the reference pointing to the state we have seen after calling `fetch_changes()`.
     pub fn last_seen_reference(
         &self,
     ) -> Result<gix::Reference<'_>, gix::reference::find::existing::Error> {
-        self.repo.find_reference(self.seen_ref_name)
+        self.repo.find_reference(self.config.seen_ref_name.as_str())
+    }
+
+    /// Return the reference pointing to the tree of the commit we have seen after calling `fetch_changes()`.
+    ///
+    /// It keeps the tree reachable even if the upstream history was squashed and the last seen commit was
+    /// garbage-collected, so changes can still be computed relative to it.
+    pub fn last_seen_tree_reference(
+        &self,
+    ) -> Result<gix::Reference<'_>, gix::reference::find::existing::Error> {
+        self.repo.find_reference(self.seen_tree_ref_name().as_str())
+    }
+
+    /// The name of the reference to the tree of the last seen commit, derived from `seen_ref_name`.
+    pub(crate) fn seen_tree_ref_name(&self) -> String {
+        let name = self.config.seen_ref_name.as_str();
+        format!(
+            "{LAST_SEEN_TREES_REF_PREFIX}{}",
+            name.strip_prefix("refs/").unwrap_or(name)
+        )
     }
 }
 
+/// Conversion
+impl Index {
+    /// Turn this instance into one that can be shared across threads.
+    pub fn into_sync(self) -> ThreadSafeIndex {
+        ThreadSafeIndex {
+            config: self.config,
+            repo: self.repo.into_sync(),
+        }
+    }
+}
+
+impl From<Index> for ThreadSafeIndex {
+    fn from(index: Index) -> Self {
+        index.into_sync()
+    }
+}
+
+/// Conversion
+impl ThreadSafeIndex {
+    /// Return an [`Index`] with the same configuration for use in the current thread.
+    ///
+    /// Its object cache is sized according to [`Config::object_cache_size`].
+    pub fn to_thread_local(&self) -> Index {
+        let mut repo = self.repo.to_thread_local();
+        self.config.configure_object_cache(&mut repo);
+        Index {
+            config: self.config.clone(),
+            repo,
+        }
+    }
+}
+
+impl From<ThreadSafeIndex> for Index {
+    fn from(index: ThreadSafeIndex) -> Self {
+        index.to_thread_local()
+    }
+}
+
+/// Run `f` on the blocking thread pool of the `tokio` runtime, with its interrupt flag being set if the returned
+/// future is dropped before completion.
+#[cfg(feature = "async")]
+pub(crate) async fn spawn_blocking<T>(
+    f: impl FnOnce(&std::sync::atomic::AtomicBool) -> T + Send + 'static,
+) -> T
+where
+    T: Send + 'static,
+{
+    struct InterruptOnDrop(std::sync::Arc<std::sync::atomic::AtomicBool>);
+    impl Drop for InterruptOnDrop {
+        fn drop(&mut self) {
+            self.0.store(true, std::sync::atomic::Ordering::Relaxed);
+        }
+    }
+
+    let should_interrupt = std::sync::Arc::new(std::sync::atomic::AtomicBool::default());
+    let _interrupt_on_drop = InterruptOnDrop(should_interrupt.clone());
+    tokio::task::spawn_blocking(move || f(&should_interrupt))
+        .await
+        .unwrap_or_else(|err| std::panic::resume_unwind(err.into_panic()))
+}
+
+/// Configurable index repo loading & cloning
+pub mod builder;
+/// Import and export of index updates as git bundles
+pub mod bundle;
 /// Main index diff functionality
 pub mod diff;
 /// initial index repo loading & cloning
 pub mod init;
+/// Detection of dependencies that new versions of existing crates didn't have before
+pub mod supply_chain;
+
+pub use builder::IndexBuilder;
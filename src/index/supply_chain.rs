@@ -0,0 +1,250 @@
+use crate::index::diff;
+use crate::index::diff::delegate::version_from_json_line;
+use crate::{Change, CrateVersion, Dependency, Index};
+use bstr::{BStr, ByteSlice};
+use gix::prelude::ObjectIdExt;
+use smartstring::alias::String as SmolString;
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, SystemTime};
+
+/// The error returned by the [`Analyzer`].
+#[derive(Debug, thiserror::Error)]
+#[allow(missing_docs)]
+pub enum Error {
+    #[error("Couldn't find an object to learn about the history of a crate")]
+    FindObject(#[from] Box<gix::object::find::existing::Error>),
+    #[error("Couldn't get the tree of a commit")]
+    PeelToTree(#[from] Box<gix::object::peel::to_kind::Error>),
+    #[error("Couldn't traverse the commits to learn when a crate was published")]
+    RevWalk(#[from] Box<gix::revision::walk::Error>),
+    #[error(transparent)]
+    RevWalkIter(#[from] Box<gix::revision::walk::iter::Error>),
+    #[error("Couldn't decode a commit to learn its commit time")]
+    DecodeCommit(#[from] Box<gix::objs::decode::Error>),
+    #[error(transparent)]
+    Index(#[from] Box<diff::Error>),
+}
+
+impl_from_boxed!(gix::object::find::existing::Error => Error::FindObject);
+impl_from_boxed!(gix::object::peel::to_kind::Error => Error::PeelToTree);
+impl_from_boxed!(gix::revision::walk::Error => Error::RevWalk);
+impl_from_boxed!(gix::revision::walk::iter::Error => Error::RevWalkIter);
+impl_from_boxed!(gix::objs::decode::Error => Error::DecodeCommit);
+impl_from_boxed!(diff::Error => Error::Index);
+
+/// A dependency of a crate version which none of the versions of the same crate that were published before it had.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NewDependency {
+    /// The name of the crate whose new version introduced the dependency.
+    pub crate_name: SmolString,
+    /// The version that introduced the dependency.
+    pub crate_version: SmolString,
+    /// The dependency as it is declared by the new version.
+    pub dependency: Dependency,
+    /// How long the crate of the dependency has been in the index.
+    pub dependency_age: DependencyAge,
+}
+
+/// When the crate of a dependency was added to the index, as far as the available history tells.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DependencyAge {
+    /// The crate was added by a commit with the given time, which is when it was first published.
+    Published(SystemTime),
+    /// The crate was already present in the oldest available commit, which has the given time.
+    ///
+    /// This is typical for all but the youngest crates, as the history of the crates.io index is squashed regularly.
+    PublishedAtOrBefore(SystemTime),
+    /// The crate isn't in the index, for instance because it was deleted.
+    Unknown,
+}
+
+impl DependencyAge {
+    /// Return how long ago the crate was published relative to `now`, which is the least it could be
+    /// if it was [published before](DependencyAge::PublishedAtOrBefore) the given time, or `None` if it is unknown.
+    pub fn age(&self, now: SystemTime) -> Option<Duration> {
+        match self {
+            DependencyAge::Published(time) | DependencyAge::PublishedAtOrBefore(time) => {
+                Some(now.duration_since(*time).unwrap_or_default())
+            }
+            DependencyAge::Unknown => None,
+        }
+    }
+}
+
+/// Finds dependencies that new versions of crates didn't have before, to learn about changes to the supply chain
+/// of their dependents, as created by [`Index::supply_chain_analyzer()`].
+///
+/// It's up to the caller to decide which crates are used widely enough to be worth looking at, and which
+/// kinds of dependencies matter.
+pub struct Analyzer<'repo> {
+    repo: &'repo gix::Repository,
+    /// The tree to read the versions of crates from.
+    tree: gix::Tree<'repo>,
+    /// The commit to start the traversal from when determining the age of crates.
+    tip: gix::hash::ObjectId,
+    /// The first-parent ancestry of `tip` along with their commit time, starting at `tip`, once it was needed.
+    commits: Option<Vec<(gix::hash::ObjectId, gix::date::SecondsSinceUnixEpoch)>>,
+    /// The age of all crates that were seen as dependency so far, by name.
+    ages: HashMap<String, DependencyAge>,
+}
+
+/// Supply chain analysis
+impl Index {
+    /// Return an analyzer which learns about the versions of crates and their age from the history of commit `at`,
+    /// which typically is the commit that changes to analyze were obtained up to.
+    pub fn supply_chain_analyzer(
+        &self,
+        at: impl Into<gix::hash::ObjectId>,
+    ) -> Result<Analyzer<'_>, Error> {
+        let tip = at.into();
+        Ok(Analyzer {
+            repo: &self.repo,
+            tree: tip.attach(&self.repo).object()?.peel_to_tree()?,
+            tip,
+            commits: None,
+            ages: HashMap::new(),
+        })
+    }
+}
+
+impl Analyzer<'_> {
+    /// Return the dependencies of all versions that were added by `changes` which none of the versions of the same crate
+    /// that were published before them had, along with the age of the dependency.
+    ///
+    /// Dependencies are identified by the crate they refer to, so renaming a dependency or changing its kind doesn't count,
+    /// and versions that are the first of their crate are skipped as all of their dependencies are new.
+    pub fn new_dependencies<'a>(
+        &mut self,
+        changes: impl IntoIterator<Item = &'a Change>,
+    ) -> Result<Vec<NewDependency>, Error> {
+        let mut out = Vec::new();
+        let mut versions_by_crate = HashMap::<SmolString, Vec<CrateVersion>>::new();
+        for version in changes.into_iter().filter_map(Change::added) {
+            if !versions_by_crate.contains_key(&version.name) {
+                let versions = self.versions(&version.name)?;
+                versions_by_crate.insert(version.name.clone(), versions);
+            }
+            let versions = &versions_by_crate[&version.name];
+            let Some(position) = versions
+                .iter()
+                .position(|v| v.checksum == version.checksum)
+                .filter(|position| *position > 0)
+            else {
+                continue;
+            };
+            let mut known: HashSet<_> = versions[..position]
+                .iter()
+                .flat_map(|v| v.dependencies.iter().map(dependency_crate))
+                .collect();
+            for dependency in &version.dependencies {
+                if !known.insert(dependency_crate(dependency)) {
+                    continue;
+                }
+                out.push(NewDependency {
+                    crate_name: version.name.clone(),
+                    crate_version: version.version.clone(),
+                    dependency: dependency.clone(),
+                    dependency_age: self.dependency_age(dependency_crate(dependency))?,
+                });
+            }
+        }
+        Ok(out)
+    }
+
+    /// Return when the crate `name` was added to the index.
+    ///
+    /// Note that this assumes crates aren't deleted and added back later.
+    pub fn dependency_age(&mut self, name: &str) -> Result<DependencyAge, Error> {
+        let name = name.to_lowercase();
+        if let Some(age) = self.ages.get(&name) {
+            return Ok(*age);
+        }
+        let age = if let Some(path) = crate_path(&name)
+            && self.tree.lookup_entry_by_path(&path)?.is_some()
+        {
+            let repo = self.repo;
+            let commits = self.commits()?;
+            // The index of the first commit, from newest to oldest, that doesn't have the crate anymore.
+            let (mut present, mut absent) = (0, commits.len());
+            while absent - present > 1 {
+                let mid = present + (absent - present) / 2;
+                let tree = commits[mid].0.attach(repo).object()?.peel_to_tree()?;
+                if tree.lookup_entry_by_path(&path)?.is_some() {
+                    present = mid;
+                } else {
+                    absent = mid;
+                }
+            }
+            let time = |seconds: gix::date::SecondsSinceUnixEpoch| {
+                SystemTime::UNIX_EPOCH + Duration::from_secs(seconds.max(0) as u64)
+            };
+            let published = time(commits[present].1);
+            if absent == commits.len() {
+                DependencyAge::PublishedAtOrBefore(published)
+            } else {
+                DependencyAge::Published(published)
+            }
+        } else {
+            DependencyAge::Unknown
+        };
+        self.ages.insert(name, age);
+        Ok(age)
+    }
+
+    /// Return all versions of the crate `name`, in the order they were published, or no version if it doesn't exist.
+    fn versions(&self, name: &str) -> Result<Vec<CrateVersion>, Error> {
+        let Some(path) = crate_path(&name.to_lowercase()) else {
+            return Ok(Vec::new());
+        };
+        let Some(entry) = self.tree.lookup_entry_by_path(&path)? else {
+            return Ok(Vec::new());
+        };
+        let blob = entry.object()?;
+        let file_name: &BStr = path.as_bytes().as_bstr();
+        let mut versions = Vec::new();
+        for line in blob.data.lines() {
+            versions.push(version_from_json_line(line, file_name).map_err(Box::new)?);
+        }
+        Ok(versions)
+    }
+
+    fn commits(
+        &mut self,
+    ) -> Result<&[(gix::hash::ObjectId, gix::date::SecondsSinceUnixEpoch)], Error> {
+        if self.commits.is_none() {
+            let mut commits = Vec::new();
+            for info in self
+                .tip
+                .attach(self.repo)
+                .ancestors()
+                .first_parent_only()
+                .all()?
+            {
+                let info = info?;
+                commits.push((info.id, info.object()?.committer()?.seconds()));
+            }
+            self.commits = Some(commits);
+        }
+        Ok(self.commits.as_deref().unwrap_or_default())
+    }
+}
+
+/// Return the name of the crate that `dependency` refers to, which differs from its name if it was renamed.
+fn dependency_crate(dependency: &Dependency) -> &str {
+    dependency.package.as_deref().unwrap_or(&dependency.name)
+}
+
+/// Return the path to the file of the crate with the lower-case `name` in the index, or `None` if no crate
+/// can have this name as it's empty or not ASCII.
+fn crate_path(name: &str) -> Option<String> {
+    if !name.is_ascii() {
+        return None;
+    }
+    Some(match name.len() {
+        0 => return None,
+        1 => format!("1/{name}"),
+        2 => format!("2/{name}"),
+        3 => format!("3/{}/{name}", &name[..1]),
+        _ => format!("{}/{}/{name}", &name[..2], &name[2..4]),
+    })
+}
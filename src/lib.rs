@@ -4,10 +4,21 @@
 //! [crates-io-cli](https://github.com/Byron/crates-io-cli-rs/blob/b7a39ad8ef68adb81b2d8a7e552cb0a2a73f7d5b/src/main.rs#L62)
 #![deny(missing_docs, unsafe_code)]
 
+/// Matching of new crate versions against security advisories.
+#[cfg(feature = "advisory")]
+pub mod advisory;
 /// Access to the main `Index` type and related functionality.
 pub mod index;
+/// Checking the packages of a `Cargo.lock` file for yanks and updates.
+#[cfg(feature = "lockfile")]
+pub mod lockfile;
 mod types;
 /// Access to all `gitoxide` functionality.
 pub use gix;
 
-pub use types::{Change, CrateVersion, Dependency, DependencyKind, Index};
+#[cfg(feature = "semver")]
+pub use types::RequirementChange;
+pub use types::{
+    Change, CrateVersion, Dependency, DependencyChange, DependencyKind, FeatureChange, Index,
+    ThreadSafeIndex,
+};
@@ -0,0 +1,174 @@
+use crate::{Change, CrateVersion};
+use semver::{Comparator, Op, Version, VersionReq};
+use std::path::Path;
+
+/// The error returned when reading a lockfile.
+#[derive(Debug, thiserror::Error)]
+#[allow(missing_docs)]
+pub enum Error {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error("Couldn't parse the lockfile")]
+    Parse(#[from] toml::de::Error),
+    #[error("The version {version:?} of package {name:?} is invalid")]
+    Version {
+        source: semver::Error,
+        name: String,
+        version: String,
+    },
+}
+
+/// The sources that refer to the crates.io index, as used in `Cargo.lock` files.
+const CRATES_IO_SOURCES: &[&str] = &[
+    "registry+https://github.com/rust-lang/crates.io-index",
+    "sparse+https://index.crates.io/",
+];
+
+/// A package as it is locked in a `Cargo.lock` file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LockedPackage {
+    /// The name of the crate.
+    pub name: String,
+    /// The version the crate is locked to.
+    pub version: Version,
+    /// Where the crate is obtained from, or `None` for crates in the workspace or that are referred to by path.
+    pub source: Option<String>,
+}
+
+impl LockedPackage {
+    /// Return `true` if this package is obtained from crates.io.
+    pub fn is_from_crates_io(&self) -> bool {
+        self.source
+            .as_deref()
+            .is_some_and(|source| CRATES_IO_SOURCES.contains(&source))
+    }
+
+    /// Return `true` if `version` is higher than the locked version, but can be updated to without changing `Cargo.toml`,
+    /// as it is semver-compatible.
+    pub fn is_compatible_update(&self, version: &Version) -> bool {
+        let compatible = VersionReq {
+            comparators: vec![Comparator {
+                op: Op::Caret,
+                major: self.version.major,
+                minor: Some(self.version.minor),
+                patch: Some(self.version.patch),
+                pre: self.version.pre.clone(),
+            }],
+        };
+        *version > self.version && compatible.matches(version)
+    }
+}
+
+/// Something that happened to a locked package, as returned by [`Lockfile::check()`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Finding<'lock> {
+    /// The locked version was yanked.
+    Yanked {
+        /// The package whose locked version was yanked.
+        locked: &'lock LockedPackage,
+    },
+    /// A higher version was published that the package could be updated to without changing `Cargo.toml`.
+    CompatibleUpdate {
+        /// The package that can be updated.
+        locked: &'lock LockedPackage,
+        /// The new version.
+        version: Version,
+    },
+}
+
+/// The parts of a `Cargo.lock` file we are interested in.
+#[derive(serde::Deserialize)]
+struct Document {
+    #[serde(default)]
+    package: Vec<Package>,
+}
+
+/// A `[[package]]` table of a `Cargo.lock` file.
+#[derive(serde::Deserialize)]
+struct Package {
+    name: String,
+    version: String,
+    source: Option<String>,
+}
+
+/// The packages of a `Cargo.lock` file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Lockfile {
+    /// All packages in the order they are listed in the lockfile.
+    pub packages: Vec<LockedPackage>,
+}
+
+impl Lockfile {
+    /// Read the `Cargo.lock` file at `path`.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Error> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    /// Parse the `content` of a `Cargo.lock` file.
+    pub fn parse(content: &str) -> Result<Self, Error> {
+        let document: Document = toml::from_str(content)?;
+        let mut packages = Vec::with_capacity(document.package.len());
+        for Package {
+            name,
+            version,
+            source,
+        } in document.package
+        {
+            packages.push(LockedPackage {
+                version: Version::parse(&version).map_err(|source| Error::Version {
+                    source,
+                    name: name.clone(),
+                    version,
+                })?,
+                name,
+                source,
+            });
+        }
+        Ok(Lockfile { packages })
+    }
+
+    /// Return what happened to the packages from crates.io according to `changes`, which is when their locked version
+    /// was yanked, or when a version was published that they could be updated to.
+    ///
+    /// Yanked versions aren't considered to be updates.
+    pub fn check<'a>(&self, changes: impl IntoIterator<Item = &'a Change>) -> Vec<Finding<'_>> {
+        let is_locked = |package: &LockedPackage, version: &CrateVersion| {
+            package.name == version.name.as_str() && package.is_from_crates_io()
+        };
+        let mut findings = Vec::new();
+        for change in changes {
+            match change {
+                Change::Yanked(version) | Change::AddedAndYanked(version) => {
+                    let yanked = version.version();
+                    findings.extend(
+                        self.packages
+                            .iter()
+                            .filter(|package| {
+                                is_locked(package, version) && package.version == yanked
+                            })
+                            .map(|locked| Finding::Yanked { locked }),
+                    );
+                }
+                Change::Added { .. } | Change::CrateCreated { .. } => {
+                    for version in change.versions().iter().filter(|v| !v.yanked) {
+                        let new = version.version();
+                        findings.extend(
+                            self.packages
+                                .iter()
+                                .filter(|package| {
+                                    is_locked(package, version)
+                                        && package.is_compatible_update(&new)
+                                })
+                                .map(|locked| Finding::CompatibleUpdate {
+                                    locked,
+                                    version: new.clone(),
+                                }),
+                        );
+                    }
+                }
+                _ => {}
+            }
+        }
+        findings
+    }
+}
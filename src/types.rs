@@ -1,29 +1,45 @@
 use std::collections::HashMap;
 
-use bstr::BString;
 use smartstring::alias::String as SmolString;
 use std::hash::Hash;
 use std::{fmt, slice};
 
 /// A wrapper for a repository of the crates.io index.
 pub struct Index {
-    /// The name and path of the reference used to keep track of the last seen state of the
-    /// crates.io repository. The default value is `refs/heads/crates-index-diff_last-seen`.
-    pub seen_ref_name: &'static str,
-    /// The name of the branch to fetch. This value also affects the tracking branch.
-    pub branch_name: &'static str,
-    /// The name of the symbolic name of the remote to fetch from.
-    /// If `None`, obtain the remote name from the configuration of the currently checked-out branch.
-    pub remote_name: Option<BString>,
+    /// The configuration for fetching and diffing, validated by [`IndexBuilder::build()`](crate::index::IndexBuilder::build()).
+    pub(crate) config: crate::index::Config,
     /// The git repository to use for diffing
     pub(crate) repo: gix::Repository,
 }
 
+/// A thread-safe version of [`Index`] which can be cloned and shared across threads.
+///
+/// Use [`ThreadSafeIndex::to_thread_local()`] to obtain an [`Index`] for use within the current thread,
+/// which is cheap as it shares all caches of the underlying repository.
+#[derive(Clone)]
+pub struct ThreadSafeIndex {
+    pub(crate) config: crate::index::Config,
+    pub(crate) repo: gix::ThreadSafeRepository,
+}
+
 /// Identify a kind of change that occurred to a crate
 #[derive(Clone, Eq, PartialEq, Debug)]
 pub enum Change {
     /// A crate version was added.
-    Added(CrateVersion),
+    Added {
+        /// The version that was added.
+        version: CrateVersion,
+        /// How the dependencies changed compared to the highest version of the crate before this one.
+        ///
+        /// This is only set for versions that were added to an existing crate if [`Config::attach_dependency_changes`](crate::index::Config::attach_dependency_changes)
+        /// is enabled, and if there is a lower version to compare to.
+        dependency_changes: Option<Vec<DependencyChange>>,
+        /// How the features changed compared to the highest version of the crate before this one.
+        ///
+        /// This is only set for versions that were added to an existing crate if [`Config::attach_feature_changes`](crate::index::Config::attach_feature_changes)
+        /// is enabled, and if there is a lower version to compare to.
+        feature_changes: Option<Vec<FeatureChange>>,
+    },
     /// A crate version was unyanked.
     Unyanked(CrateVersion),
     /// A crate version was added in a yanked state.
@@ -33,6 +49,16 @@ pub enum Change {
     AddedAndYanked(CrateVersion),
     /// A crate version was yanked.
     Yanked(CrateVersion),
+    /// The name of the crate whose file was added, which implies all versions were added as well.
+    ///
+    /// This is only emitted if [`Config::report_crate_creations`](crate::index::Config::report_crate_creations) is enabled, otherwise each version
+    /// is reported as [`Added`](Change::Added) or [`AddedAndYanked`](Change::AddedAndYanked).
+    CrateCreated {
+        /// The name of the new crate.
+        name: String,
+        /// All of its versions that were added along with the file, some of which may be yanked.
+        versions: Vec<CrateVersion>,
+    },
     /// The name of the crate whose file was deleted, which implies all versions were deleted as well.
     CrateDeleted {
         /// The name of the deleted crate.
@@ -45,13 +71,45 @@ pub enum Change {
     /// Note that this is equivalent to deleting a line from a crates version file.
     /// Should more than one lines be removed per commit, the order of these changes is nondeterministic.
     VersionDeleted(CrateVersion),
+    /// The upstream history was squashed, so the last-seen commit `from` is no longer an ancestor of the new tip `to`.
+    ///
+    /// This event is emitted before all other changes, which are then the net changes between the trees of both commits.
+    /// The history before the squash is typically archived upstream in `snapshot-YYYY-MM-DD` branches.
+    Squashed {
+        /// The last-seen commit which is not part of the current history anymore.
+        from: gix::hash::ObjectId,
+        /// The new tip of the remote branch.
+        to: gix::hash::ObjectId,
+    },
 }
 
 impl Change {
     /// Return the added crate, if this is this kind of change.
     pub fn added(&self) -> Option<&CrateVersion> {
         match self {
-            Change::Added(v) | Change::AddedAndYanked(v) => Some(v),
+            Change::Added { version, .. } | Change::AddedAndYanked(version) => Some(version),
+            _ => None,
+        }
+    }
+
+    /// Return how the dependencies of the added crate changed compared to its predecessor, if this is this kind of change
+    /// and they were compared.
+    pub fn dependency_changes(&self) -> Option<&[DependencyChange]> {
+        match self {
+            Change::Added {
+                dependency_changes, ..
+            } => dependency_changes.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// Return how the features of the added crate changed compared to its predecessor, if this is this kind of change
+    /// and they were compared.
+    pub fn feature_changes(&self) -> Option<&[FeatureChange]> {
+        match self {
+            Change::Added {
+                feature_changes, ..
+            } => feature_changes.as_deref(),
             _ => None,
         }
     }
@@ -72,6 +130,14 @@ impl Change {
         }
     }
 
+    /// Return the new crate, if this is this kind of change.
+    pub fn crate_created(&self) -> Option<(&str, &[CrateVersion])> {
+        match self {
+            Change::CrateCreated { name, versions } => Some((name.as_str(), versions)),
+            _ => None,
+        }
+    }
+
     /// Return the deleted crate, if this is this kind of change.
     pub fn crate_deleted(&self) -> Option<(&str, &[CrateVersion])> {
         match self {
@@ -88,19 +154,31 @@ impl Change {
         }
     }
 
+    /// Return the last-seen commit and the new tip if the upstream history was squashed, if this is this kind of change.
+    pub fn squashed(&self) -> Option<(gix::hash::ObjectId, gix::hash::ObjectId)> {
+        match self {
+            Change::Squashed { from, to } => Some((*from, *to)),
+            _ => None,
+        }
+    }
+
     /// Returns all versions affected by this change.
     ///
     /// The returned slice usually has length 1.
     /// However, if a crate was purged from the index by an admin,
-    /// all versions of the purged crate are returned.
+    /// all versions of the purged crate are returned, and the same is true for all versions of a new crate.
+    /// If the upstream history was squashed, the slice is empty.
     pub fn versions(&self) -> &[CrateVersion] {
         match self {
-            Change::Added(v)
+            Change::Added { version: v, .. }
             | Change::Unyanked(v)
             | Change::AddedAndYanked(v)
             | Change::Yanked(v)
             | Change::VersionDeleted(v) => slice::from_ref(v),
-            Change::CrateDeleted { versions, .. } => versions,
+            Change::CrateCreated { versions, .. } | Change::CrateDeleted { versions, .. } => {
+                versions
+            }
+            Change::Squashed { .. } => &[],
         }
     }
 }
@@ -111,12 +189,14 @@ impl fmt::Display for Change {
             f,
             "{}",
             match *self {
-                Change::Added(_) => "added",
+                Change::Added { .. } => "added",
                 Change::Yanked(_) => "yanked",
+                Change::CrateCreated { .. } => "crate created",
                 Change::CrateDeleted { .. } => "crate deleted",
                 Change::VersionDeleted(_) => "version deleted",
                 Change::Unyanked(_) => "unyanked",
                 Change::AddedAndYanked(_) => "added and yanked",
+                Change::Squashed { .. } => "squashed",
             }
         )
     }
@@ -170,6 +250,86 @@ impl CrateVersion {
         semver::Version::parse(&self.version)
             .expect("crate index guarantees a valid semantic version")
     }
+
+    /// Return how the dependencies of this version differ from the ones of the `previous` version of the same crate.
+    ///
+    /// Dependencies are matched by name and target, and preferably by kind as well, so a dependency whose kind
+    /// changed is reported as [`DependencyChange::Changed`].
+    pub fn dependency_changes_since(&self, previous: &CrateVersion) -> Vec<DependencyChange> {
+        let mut removed: Vec<_> = previous.dependencies.iter().collect();
+        let mut unmatched = Vec::new();
+        let mut changes = Vec::new();
+        let mut changed = |previous: &Dependency, current: &Dependency| {
+            if previous != current {
+                changes.push(DependencyChange::Changed {
+                    previous: previous.clone(),
+                    current: current.clone(),
+                })
+            }
+        };
+        for dep in &self.dependencies {
+            match removed
+                .iter()
+                .position(|prev| prev.same_as(dep) && prev.kind() == dep.kind())
+            {
+                Some(pos) => changed(removed.remove(pos), dep),
+                None => unmatched.push(dep),
+            }
+        }
+        let mut added = Vec::new();
+        for dep in unmatched {
+            match removed.iter().position(|prev| prev.same_as(dep)) {
+                Some(pos) => changed(removed.remove(pos), dep),
+                None => added.push(DependencyChange::Added(dep.clone())),
+            }
+        }
+        changes.extend(added);
+        changes.extend(
+            removed
+                .into_iter()
+                .map(|dep| DependencyChange::Removed(dep.clone())),
+        );
+        changes
+    }
+
+    /// Return how the features of this version differ from the ones of the `previous` version of the same crate,
+    /// sorted by the name of the feature.
+    ///
+    /// The order in which a feature lists what it enables doesn't matter.
+    pub fn feature_changes_since(&self, previous: &CrateVersion) -> Vec<FeatureChange> {
+        let sorted = |enables: &[String]| {
+            let mut enables = enables.to_vec();
+            enables.sort();
+            enables
+        };
+        let mut changes = Vec::new();
+        for (name, enables) in &self.features {
+            match previous.features.get(name) {
+                None => changes.push(FeatureChange::Added {
+                    name: name.clone(),
+                    enables: enables.clone(),
+                }),
+                Some(previous) if sorted(previous) != sorted(enables) => {
+                    changes.push(FeatureChange::Changed {
+                        name: name.clone(),
+                        previous: previous.clone(),
+                        current: enables.clone(),
+                    })
+                }
+                Some(_) => {}
+            }
+        }
+        for (name, enables) in &previous.features {
+            if !self.features.contains_key(name) {
+                changes.push(FeatureChange::Removed {
+                    name: name.clone(),
+                    enables: enables.clone(),
+                });
+            }
+        }
+        changes.sort_by(|a, b| a.name().cmp(b.name()));
+        changes
+    }
 }
 
 /// A single dependency of a specific crate version
@@ -213,4 +373,163 @@ impl Dependency {
         semver::VersionReq::parse(&self.required_version)
             .expect("version requirement should always be a valid SemVer")
     }
+
+    /// Return the kind of this dependency, with a missing kind meaning [`DependencyKind::Normal`].
+    fn kind(&self) -> DependencyKind {
+        self.kind.unwrap_or(DependencyKind::Normal)
+    }
+
+    /// Return `true` if `other` is the same dependency as this one, even though it might be specified differently.
+    fn same_as(&self, other: &Dependency) -> bool {
+        self.name == other.name && self.target == other.target
+    }
+}
+
+/// A change to the dependencies of a crate version compared to another version of the same crate.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum DependencyChange {
+    /// A dependency that the previous version didn't have.
+    Added(Dependency),
+    /// A dependency of the previous version that was removed.
+    Removed(Dependency),
+    /// A dependency that both versions have, but which is specified differently, like its version requirement,
+    /// kind or whether it's optional.
+    Changed {
+        /// The dependency of the previous version.
+        previous: Dependency,
+        /// The dependency of the current version.
+        current: Dependency,
+    },
+}
+
+impl DependencyChange {
+    /// Return how the version requirement changed, or `None` if this isn't a [`DependencyChange::Changed`]
+    /// or if the requirement is the same.
+    #[cfg(feature = "semver")]
+    pub fn requirement_change(&self) -> Option<RequirementChange> {
+        use std::cmp::Ordering;
+        let DependencyChange::Changed { previous, current } = self else {
+            return None;
+        };
+        if previous.required_version == current.required_version {
+            return None;
+        }
+        let bounds = |req: &str| {
+            semver::VersionReq::parse(req)
+                .ok()
+                .and_then(|req| bounds(&req))
+        };
+        let (Some((prev_lower, prev_upper)), Some((lower, upper))) = (
+            bounds(&previous.required_version),
+            bounds(&current.required_version),
+        ) else {
+            return Some(RequirementChange::Other);
+        };
+        Some(match (lower.cmp(&prev_lower), upper.cmp(&prev_upper)) {
+            (Ordering::Equal, Ordering::Equal) => RequirementChange::Equivalent,
+            (Ordering::Less | Ordering::Equal, Ordering::Greater | Ordering::Equal) => {
+                RequirementChange::Loosened
+            }
+            (Ordering::Greater | Ordering::Equal, Ordering::Less | Ordering::Equal) => {
+                RequirementChange::Tightened
+            }
+            _ => RequirementChange::Other,
+        })
+    }
+}
+
+/// A change to the features of a crate version compared to another version of the same crate.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum FeatureChange {
+    /// A feature that the previous version didn't have.
+    Added {
+        /// The name of the feature.
+        name: String,
+        /// The features and optional dependencies it enables.
+        enables: Vec<String>,
+    },
+    /// A feature of the previous version that was removed, which can break crates that use it.
+    Removed {
+        /// The name of the feature.
+        name: String,
+        /// The features and optional dependencies it enabled.
+        enables: Vec<String>,
+    },
+    /// A feature that both versions have, but which enables something else now.
+    Changed {
+        /// The name of the feature.
+        name: String,
+        /// The features and optional dependencies it enabled in the previous version.
+        previous: Vec<String>,
+        /// The features and optional dependencies it enables in the current version.
+        current: Vec<String>,
+    },
+}
+
+impl FeatureChange {
+    /// Return the name of the feature that changed.
+    pub fn name(&self) -> &str {
+        match self {
+            FeatureChange::Added { name, .. }
+            | FeatureChange::Removed { name, .. }
+            | FeatureChange::Changed { name, .. } => name,
+        }
+    }
+}
+
+/// The way a version requirement of a dependency changed, as returned by [`DependencyChange::requirement_change()`].
+#[cfg(feature = "semver")]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum RequirementChange {
+    /// The requirement is written differently, but allows the same versions.
+    Equivalent,
+    /// The requirement allows all versions it allowed before, and more, like `^1.2` becoming `>=1.2`.
+    Loosened,
+    /// The requirement allows a subset of the versions it allowed before, like `^1.2` becoming `^1.3`.
+    Tightened,
+    /// The requirement allows some versions it didn't allow before, but not others it did, like `^1` becoming `^2`,
+    /// or one of the requirements isn't valid.
+    Other,
+}
+
+/// A version without pre-release or build metadata, for comparisons.
+#[cfg(feature = "semver")]
+pub(crate) type Triple = (u64, u64, u64);
+
+/// Return the lowest version `req` matches, and the lowest version above that it doesn't match anymore,
+/// ignoring pre-releases, or `None` if `req` contains an unknown operator.
+#[cfg(feature = "semver")]
+pub(crate) fn bounds(req: &semver::VersionReq) -> Option<(Triple, Triple)> {
+    use semver::Op;
+    const UNBOUNDED: Triple = (u64::MAX, u64::MAX, u64::MAX);
+    let (mut lower, mut upper) = ((0, 0, 0), UNBOUNDED);
+    for c in &req.comparators {
+        let min = (c.major, c.minor.unwrap_or(0), c.patch.unwrap_or(0));
+        // The first version after all versions matching the specified components.
+        let next = match (c.minor, c.patch) {
+            (None, _) => (c.major.saturating_add(1), 0, 0),
+            (Some(minor), None) => (c.major, minor.saturating_add(1), 0),
+            (Some(minor), Some(patch)) => (c.major, minor, patch.saturating_add(1)),
+        };
+        let (lo, hi) = match c.op {
+            Op::Exact | Op::Wildcard => (min, next),
+            Op::Greater => (next, UNBOUNDED),
+            Op::GreaterEq => (min, UNBOUNDED),
+            Op::Less => ((0, 0, 0), min),
+            Op::LessEq => ((0, 0, 0), next),
+            Op::Tilde => match c.minor {
+                None => (min, next),
+                Some(minor) => (min, (c.major, minor.saturating_add(1), 0)),
+            },
+            Op::Caret => match (c.major, c.minor, c.patch) {
+                (0, Some(0), Some(_)) | (0, None, _) => (min, next),
+                (0, Some(minor), _) => (min, (0, minor.saturating_add(1), 0)),
+                (major, _, _) => (min, (major.saturating_add(1), 0, 0)),
+            },
+            _ => return None,
+        };
+        lower = lower.max(lo);
+        upper = upper.min(hi);
+    }
+    Some((lower, upper))
 }
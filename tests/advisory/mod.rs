@@ -0,0 +1,225 @@
+use crates_index_diff::advisory::{Advisory, Database, Error};
+use crates_index_diff::{Change, CrateVersion, Dependency};
+use gix_testtools::tempfile::TempDir;
+use semver::{Version, VersionReq};
+use std::path::Path;
+
+#[test]
+fn load_from_directory() -> crate::Result {
+    let dir = advisory_db()?;
+    let db = Database::load(dir.path())?;
+
+    let bstr = db.advisories("bstr");
+    assert_eq!(bstr.len(), 1);
+    assert_eq!(bstr[0].id, "RUSTSEC-2099-0001");
+    assert_eq!(bstr[0].title.as_deref(), Some("Out-of-bounds read"));
+    assert_eq!(bstr[0].informational, None);
+    assert_eq!(bstr[0].patched, [VersionReq::parse(">= 0.2.14")?]);
+    assert_eq!(bstr[0].unaffected, [VersionReq::parse("< 0.2.0")?]);
+
+    let legacy = db.advisories("legacy");
+    assert_eq!(legacy.len(), 1, "advisories in TOML files are supported");
+    assert_eq!(legacy[0].title.as_deref(), Some("Legacy format"));
+    assert_eq!(legacy[0].informational.as_deref(), Some("unmaintained"));
+
+    assert!(
+        db.advisories("thiserror").is_empty(),
+        "withdrawn advisories are skipped"
+    );
+
+    assert!(db.advisories("broken").is_empty());
+    assert_eq!(
+        db.errors()
+            .iter()
+            .map(|err| match err {
+                Error::Parse { path, .. } => path.strip_prefix(dir.path()).ok(),
+                Error::Io { .. } => None,
+            })
+            .collect::<Vec<_>>(),
+        [Some(Path::new("crates/broken/RUSTSEC-2099-0004.md"))],
+        "malformed advisories are skipped without failing the whole load"
+    );
+    Ok(())
+}
+
+#[test]
+fn vulnerable_versions_and_requirements() -> crate::Result {
+    let advisory = Advisory {
+        id: "RUSTSEC-2099-0001".into(),
+        package: "a".into(),
+        title: None,
+        informational: None,
+        patched: vec![
+            VersionReq::parse(">= 1.2.5, < 1.3.0")?,
+            VersionReq::parse(">= 1.3.2")?,
+        ],
+        unaffected: vec![VersionReq::parse("< 1.0.0")?],
+    };
+    for (version, expected) in [
+        ("0.9.0", false),
+        ("1.0.0", true),
+        ("1.2.5", false),
+        ("1.3.1", true),
+        ("2.0.0", false),
+    ] {
+        assert_eq!(
+            advisory.is_vulnerable(&Version::parse(version)?),
+            expected,
+            "{version}"
+        );
+    }
+    for (req, expected) in [
+        ("^0.9", false),
+        ("^1.2.5", true),
+        ("~1.2.5", false),
+        ("^1.3.2", false),
+        ("1.3.1", true),
+        ("^1", true),
+        (">= 1.2.7, < 1.3", false),
+        ("*", true),
+        ("^2", false),
+    ] {
+        assert_eq!(
+            advisory.affects(&VersionReq::parse(req)?),
+            expected,
+            "{req}"
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn check_new_versions() -> crate::Result {
+    let db = Database::load(advisory_db()?.path())?;
+    let version = |name: &str, yanked: bool, dependencies: &[(&str, &str)]| CrateVersion {
+        name: name.into(),
+        version: "1.0.0".into(),
+        yanked,
+        dependencies: dependencies
+            .iter()
+            .map(|(name, req)| Dependency {
+                name: (*name).into(),
+                required_version: (*req).into(),
+                features: Vec::new(),
+                optional: false,
+                default_features: true,
+                target: None,
+                kind: None,
+                package: None,
+            })
+            .collect(),
+        ..Default::default()
+    };
+    let added = |version: CrateVersion| Change::Added {
+        version,
+        dependency_changes: None,
+        feature_changes: None,
+    };
+    let changes = [
+        added(version("affected", false, &[("bstr", "^0.2.13")])),
+        added(version("patched", false, &[("bstr", "^0.2.14")])),
+        added(version("invalid", false, &[("bstr", "not a requirement")])),
+        Change::AddedAndYanked(version("yanked", true, &[("bstr", "^0.2.13")])),
+        Change::Yanked(version("not-new", true, &[("bstr", "^0.2.13")])),
+        Change::CrateCreated {
+            name: "new".into(),
+            versions: vec![version(
+                "new",
+                false,
+                &[("legacy", "1"), ("other", "1"), ("bstr", "0.2")],
+            )],
+        },
+    ];
+
+    let alerts = db.check(&changes);
+    assert_eq!(
+        alerts
+            .iter()
+            .map(|alert| (
+                alert.crate_name.as_str(),
+                alert.dependency.name.as_str(),
+                alert.advisory.id.as_str()
+            ))
+            .collect::<Vec<_>>(),
+        [
+            ("affected", "bstr", "RUSTSEC-2099-0001"),
+            ("new", "bstr", "RUSTSEC-2099-0001"),
+        ],
+        "informational advisories like the one for `legacy` don't name vulnerable versions"
+    );
+    Ok(())
+}
+
+fn advisory_db() -> crate::Result<TempDir> {
+    let dir = TempDir::new()?;
+    let write = |path: &str, content: &str| -> std::io::Result<()> {
+        let path = dir.path().join(path);
+        std::fs::create_dir_all(path.parent().expect("in a directory"))?;
+        std::fs::write(path, content)
+    };
+    write("README.md", "# RustSec Advisory Database\n")?;
+    write(
+        "crates/bstr/RUSTSEC-2099-0001.md",
+        r#"```toml
+[advisory]
+id = "RUSTSEC-2099-0001"
+package = "bstr"
+date = "2099-01-01"
+keywords = [
+    "memory-safety", # a comment
+    "out-of-bounds",
+]
+
+[affected.functions]
+"bstr::ByteSlice::find" = ["< 0.2.14"]
+
+[versions]
+patched = [">= 0.2.14"]
+unaffected = ["< 0.2.0"]
+```
+
+# Out-of-bounds read
+
+Details.
+"#,
+    )?;
+    write(
+        "crates/legacy/RUSTSEC-2099-0002.toml",
+        r#"[advisory]
+id = "RUSTSEC-2099-0002"
+package = "legacy"
+title = "Legacy format"
+informational = "unmaintained"
+description = """
+id = "not the id"
+"""
+"#,
+    )?;
+    write(
+        "crates/thiserror/RUSTSEC-2099-0003.md",
+        r#"```toml
+[advisory]
+id = "RUSTSEC-2099-0003"
+package = "thiserror"
+withdrawn = "2099-01-02"
+
+[versions]
+patched = []
+```
+"#,
+    )?;
+    write(
+        "crates/broken/RUSTSEC-2099-0004.md",
+        r#"```toml
+[advisory]
+id = "RUSTSEC-2099-0004"
+package = "broken"
+
+[versions]
+patched = [">= 1.0.0"
+```
+"#,
+    )?;
+    write(".git/config", "")?;
+    Ok(dir)
+}
@@ -1,4 +1,8 @@
 use gix_testtools::Result;
 
+#[cfg(feature = "advisory")]
+mod advisory;
 mod index;
+#[cfg(feature = "lockfile")]
+mod lockfile;
 mod version;
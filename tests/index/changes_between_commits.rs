@@ -1,5 +1,5 @@
 use crate::index::index_ro;
-use crates_index_diff::index::diff::Order;
+use crates_index_diff::index::diff::{Error, Order, coalesce, invert};
 use crates_index_diff::{Change, CrateVersion, Index};
 
 #[test]
@@ -78,7 +78,7 @@ fn addition() -> crate::Result {
     ));
     assert!(matches!(
         changes.last().expect("present"),
-        Change::Added(CrateVersion {name, ..}) if name == "gizmo"
+        Change::Added { version: CrateVersion { name, .. }, .. } if name == "gizmo"
     ));
     Ok(())
 }
@@ -146,6 +146,58 @@ fn new_version() -> crate::Result {
     Ok(())
 }
 
+#[test]
+fn dependency_and_feature_changes_are_attached_to_new_versions_if_enabled() -> crate::Result {
+    let revspec = "@^{/Updating crate `git-repository#0.22.1`}";
+    let unattached = changes(index_ro()?, revspec)?;
+    assert_eq!(
+        (
+            unattached[0].dependency_changes(),
+            unattached[0].feature_changes()
+        ),
+        (None, None),
+        "nothing is attached by default"
+    );
+
+    let mut index = index_ro()?;
+    index.set_attach_dependency_changes(true);
+    index.set_attach_feature_changes(true);
+    let previous_file = index
+        .repository()
+        .rev_parse_single(format!("{revspec}~1:gi/t-/git-repository").as_str())?
+        .object()?
+        .detach();
+    let previous: CrateVersion = previous_file
+        .data
+        .split(|b| *b == b'\n')
+        .filter(|line| !line.is_empty())
+        .map(serde_json::from_slice::<CrateVersion>)
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .max_by_key(|v| v.version())
+        .expect("there are previous versions");
+    assert_eq!(previous.version, "0.22.0");
+
+    let new_version = changes(index, revspec)?;
+    let added = new_version[0].added().expect("added");
+    assert_eq!(
+        new_version[0].dependency_changes(),
+        Some(added.dependency_changes_since(&previous).as_slice()),
+        "the version is compared to the highest one before it"
+    );
+    assert!(
+        new_version[0]
+            .dependency_changes()
+            .is_some_and(|changes| !changes.is_empty()),
+        "dependencies were changed in this release"
+    );
+    assert_eq!(
+        new_version[0].feature_changes(),
+        Some(added.feature_changes_since(&previous).as_slice())
+    );
+    Ok(())
+}
+
 #[test]
 fn yanked() -> crate::Result {
     let changes = changes(index_ro()?, ":/Yanking crate `github_release_rs#0.1.0`")?;
@@ -172,6 +224,37 @@ fn yanked_in_new_file() -> crate::Result {
     Ok(())
 }
 
+#[test]
+fn crate_creations_are_reported_if_enabled() -> crate::Result {
+    let mut index = index_ro()?;
+    index.set_report_crate_creations(true);
+    let new_file = changes(index, ":/reproduce issue #19")?;
+    assert_eq!(new_file.len(), 1);
+    let (name, versions) = new_file[0].crate_created().expect("CrateCreated event");
+    assert_eq!(name, "allowed");
+    assert_eq!(versions.len(), 1);
+    assert!(versions[0].yanked, "yanked versions are part of new crates");
+
+    let mut index = index_ro()?;
+    index.set_report_crate_creations(true);
+    let initial = changes(index, ":/initial commit")?;
+    assert!(initial.iter().all(|c| c.crate_created().is_some()));
+    assert_eq!(
+        initial.iter().map(|c| c.versions().len()).sum::<usize>(),
+        3228,
+        "the same versions as without reporting crate creations"
+    );
+
+    let mut index = index_ro()?;
+    index.set_report_crate_creations(true);
+    let new_version = changes(index, ":/Updating crate `git-repository#0.22.1`")?;
+    assert!(
+        matches!(new_version.as_slice(), [Change::Added { version, .. }] if version.name == "git-repository"),
+        "new versions of existing crates are still reported as such"
+    );
+    Ok(())
+}
+
 #[test]
 fn unyanked_crates_recognized() -> crate::Result {
     let changes = changes(index_ro()?, ":/Unyanking crate `git2mail#0.3.2`")?;
@@ -196,6 +279,216 @@ fn normalization() -> crate::Result {
     Ok(())
 }
 
+#[test]
+fn ranges_can_be_used_instead_of_two_revisions() -> crate::Result {
+    let index = index_ro()?;
+    let id = index
+        .repository()
+        .rev_parse_single(":/Updating crate `git-repository#0.22.1`")?;
+    let expected = index.changes(format!("{id}~1"), id.to_string())?;
+    assert_eq!(expected.len(), 1);
+    assert_eq!(index.changes_in_range(format!("{id}~1..{id}"))?, expected);
+    assert_eq!(
+        index.changes_in_range(format!("{id}~1...{id}"))?,
+        expected,
+        "the merge-base is the parent"
+    );
+    assert_eq!(index.changes_in_range(format!("{id}^!"))?, expected);
+
+    for (from, to) in [("@~1..@", "@"), ("@", "@~1..@")] {
+        assert!(
+            matches!(index.changes(from, to), Err(Error::NotASingleRevision { spec }) if spec == "@~1..@"),
+            "ranges can't be used as single revision"
+        );
+    }
+    for spec in ["@", "^@"] {
+        assert!(matches!(
+            index.changes_in_range(spec),
+            Err(Error::NotARange { spec: actual }) if actual == spec
+        ));
+    }
+    Ok(())
+}
+
+#[test]
+fn coalesced_changes_are_the_net_effect() -> crate::Result {
+    let index = index_ro()?;
+    let repo = index.repository();
+    let root = repo
+        .rev_parse_single("main")?
+        .ancestors()
+        .first_parent_only()
+        .all()?
+        .last()
+        .expect("at least one commit")?
+        .id;
+    let tip = repo.rev_parse_single("main")?.detach();
+    let (step_by_step, order) = index.changes_between_ancestor_commits(root, tip)?;
+    assert_eq!(order, Order::AsInCratesIndex);
+
+    let net = coalesce(step_by_step.iter().cloned());
+    assert!(
+        net.len() < step_by_step.len(),
+        "some versions were changed more than once"
+    );
+    assert_eq!(
+        flattened(net),
+        flattened(index.changes_between_commits(root, tip)?),
+        "coalescing is the same as diffing the start and the end"
+    );
+
+    let version = |version: &str, yanked: bool| CrateVersion {
+        name: "a".into(),
+        version: version.into(),
+        yanked,
+        ..Default::default()
+    };
+    let added = |version: CrateVersion| Change::Added {
+        version,
+        dependency_changes: None,
+        feature_changes: None,
+    };
+    assert_eq!(
+        coalesce([
+            added(version("1.0.0", false)),
+            Change::Yanked(version("1.0.0", true)),
+            added(version("2.0.0", false)),
+            Change::VersionDeleted(version("2.0.0", false)),
+            Change::Yanked(version("0.1.0", true)),
+            Change::Unyanked(version("0.1.0", false)),
+        ]),
+        [Change::AddedAndYanked(version("1.0.0", true))]
+    );
+    assert_eq!(
+        coalesce([
+            Change::Yanked(version("0.1.0", true)),
+            added(version("1.0.0", false)),
+            Change::CrateDeleted {
+                name: "a".into(),
+                versions: vec![version("0.1.0", true), version("1.0.0", false)],
+            },
+        ]),
+        [Change::CrateDeleted {
+            name: "a".into(),
+            versions: vec![version("0.1.0", true)],
+        }],
+        "only versions that existed before are deleted"
+    );
+    assert_eq!(
+        coalesce([
+            Change::CrateCreated {
+                name: "a".into(),
+                versions: vec![version("1.0.0", false)],
+            },
+            added(version("1.1.0", false)),
+            Change::Yanked(version("1.0.0", true)),
+        ]),
+        [Change::CrateCreated {
+            name: "a".into(),
+            versions: vec![version("1.0.0", true), version("1.1.0", false)],
+        }],
+        "versions added after the crate was created are part of it"
+    );
+    let with_attached_changes = Change::Added {
+        version: version("1.1.0", false),
+        dependency_changes: Some(Vec::new()),
+        feature_changes: Some(Vec::new()),
+    };
+    assert_eq!(
+        coalesce([
+            with_attached_changes.clone(),
+            Change::Yanked(version("1.1.0", true)),
+            Change::Unyanked(version("1.1.0", false)),
+        ]),
+        [with_attached_changes],
+        "dependency and feature changes are kept from the change that added the version"
+    );
+    Ok(())
+}
+
+#[test]
+fn coalesced_crate_changes_are_identified_by_file_name() {
+    let version = CrateVersion {
+        name: "Inflector".into(),
+        version: "0.1.0".into(),
+        ..Default::default()
+    };
+    for change in [
+        Change::CrateCreated {
+            name: "inflector".into(),
+            versions: vec![version.clone()],
+        },
+        Change::CrateDeleted {
+            name: "inflector".into(),
+            versions: vec![version.clone()],
+        },
+    ] {
+        assert_eq!(
+            coalesce([change.clone()]),
+            [change],
+            "the lower-cased file name refers to the same crate as the name of its versions"
+        );
+    }
+}
+
+#[test]
+fn inverted_changes_undo_the_original_ones() -> crate::Result {
+    let index = index_ro()?;
+    let repo = index.repository();
+    let root = repo
+        .rev_parse_single("main")?
+        .ancestors()
+        .first_parent_only()
+        .all()?
+        .last()
+        .expect("at least one commit")?
+        .id;
+    let tip = repo.rev_parse_single("main")?.detach();
+    let yanks = repo
+        .rev_parse_single("@^{/yanking ansi-color-codec 0.3.5}")?
+        .detach();
+    let deletion = repo.rev_parse_single(":/reproduce issue #20")?.detach();
+
+    for (a, b) in [(root, tip), (yanks, tip), (deletion, root), (tip, yanks)] {
+        let forward = index.changes_between_commits(a, b)?;
+        let backward = index.changes_between_commits(b, a)?;
+        assert!(!forward.is_empty());
+        assert_eq!(
+            flattened(invert(forward)),
+            flattened(backward),
+            "inverting {a}..{b} is the same as diffing {b}..{a}"
+        );
+    }
+
+    let (step_by_step, _order) = index.changes_between_ancestor_commits(root, tip)?;
+    assert_eq!(
+        flattened(coalesce(invert(step_by_step))),
+        flattened(index.changes_between_commits(tip, root)?),
+        "the inverse can also be coalesced"
+    );
+    Ok(())
+}
+
+/// Turn `changes` into a sorted list of the kind of change along with the name and version of each affected crate version,
+/// with crate deletions counting as deletions of each of their versions.
+fn flattened(changes: Vec<Change>) -> Vec<(String, String, String)> {
+    let mut out: Vec<_> = changes
+        .iter()
+        .flat_map(|change| {
+            let kind = match change {
+                Change::CrateDeleted { .. } => "version deleted".to_string(),
+                other => other.to_string(),
+            };
+            change
+                .versions()
+                .iter()
+                .map(move |v| (kind.clone(), v.name.to_string(), v.version.to_string()))
+        })
+        .collect();
+    out.sort();
+    out
+}
+
 fn changes(mut index: Index, revspec: &str) -> crate::Result<Vec<Change>> {
     let (prev, current) = {
         let repo = index.repository_mut();
@@ -1,9 +1,21 @@
-use crates_index_diff::index::diff::Order;
-use crates_index_diff::Index;
+#[cfg(feature = "async")]
+use crates_index_diff::ThreadSafeIndex;
+use crates_index_diff::index::diff::{Error, Order};
+use crates_index_diff::index::supply_chain::DependencyAge;
+use crates_index_diff::index::{
+    CloneOptions, IncompleteClone, IndexBuilder, RetryPolicy, UrlMismatch, bundle, init,
+};
+use crates_index_diff::{Change, Index};
+use gix::prelude::ObjectIdExt;
 use gix::refs::transaction::PreviousValue;
 use gix_testtools::tempfile::TempDir;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
 use std::path::PathBuf;
-use std::sync::atomic::AtomicBool;
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::Duration;
 
 mod changes_between_commits;
 
@@ -11,8 +23,12 @@ const NUM_CHANGES_SINCE_EVER: usize = 3521;
 
 #[test]
 fn peek_changes() -> crate::Result {
-    let mut index = index_ro()?;
-    index.branch_name = "main";
+    let index = index_ro()?;
+    assert_eq!(
+        index.config().branch_name,
+        "main",
+        "the default branch is detected from the clone"
+    );
     for order in [Order::ImplementationDefined, Order::AsInCratesIndex] {
         assert!(
             index.last_seen_reference().is_err(),
@@ -67,7 +83,7 @@ fn clone_if_needed() {
 
 #[test]
 fn changes_since_last_fetch() {
-    let (mut index, _tmp) = index_rw().unwrap();
+    let (index, _tmp) = index_rw().unwrap();
     let repo = index.repository();
     assert!(index.last_seen_reference().is_err(), "no marker exists");
     let num_changes_since_first_commit = index.fetch_changes().unwrap().len();
@@ -88,7 +104,7 @@ fn changes_since_last_fetch() {
     // reset to previous one
     marker
         .set_target_id(
-            repo.rev_parse(format!("{}~1", index.seen_ref_name).as_str())
+            repo.rev_parse(format!("{}~1", index.config().seen_ref_name).as_str())
                 .unwrap()
                 .single()
                 .unwrap(),
@@ -113,29 +129,7 @@ fn changes_since_last_fetch() {
     );
 
     // now the remote has squashed their history, we should still be able to get the correct changes.
-    let repo_name = "local";
-    {
-        let git_dir = repo.git_dir().to_owned();
-        let mut config = index.repository_mut().config_snapshot_mut();
-        // TODO: use `remote.save_as_to()` here, requires a way to get the mutable repo ref again.
-        config
-            .set_raw_value_by(
-                "remote",
-                Some(repo_name.into()),
-                "url",
-                git_dir.to_str().unwrap(),
-            )
-            .unwrap();
-        config
-            .set_raw_value_by(
-                "remote",
-                Some(repo_name.into()),
-                "fetch",
-                "+refs/heads/*:refs/remotes/local/*",
-            )
-            .unwrap();
-    }
-    index.remote_name = Some(repo_name.into());
+    let index = with_local_remote(index, "+refs/heads/*:refs/remotes/local/*").unwrap();
     index
         .repository()
         .reference(
@@ -150,34 +144,1171 @@ fn changes_since_last_fetch() {
             "adjust to simulate remote with new squashed history",
         )
         .unwrap();
+    let last_seen = index.last_seen_reference().unwrap().id().detach();
     let changes = index.fetch_changes().unwrap();
-    assert_eq!(changes.len(), 1);
+    assert_eq!(changes.len(), 2);
+    assert_eq!(
+        changes.first().and_then(|c| c.squashed()),
+        Some((
+            last_seen,
+            index.last_seen_reference().unwrap().id().detach()
+        )),
+        "the squash is reported first, along with the previously seen commit and the new tip"
+    );
     assert_eq!(
         changes
-            .first()
+            .get(1)
             .and_then(|c| c.added().map(|v| (v.name.as_str(), v.version.as_str()))),
         Some(("git-repository", "1.0.0")),
         "there was just one actual changes compared to the previous state"
     );
 }
 
+#[test]
+fn last_seen_tree_is_used_if_last_seen_commit_is_missing() -> crate::Result {
+    let (index, _tmp) = index_rw()?;
+    index.fetch_changes()?;
+    let repo = index.repository();
+    let tip = index.last_seen_reference()?.id().detach();
+    assert_eq!(
+        index.last_seen_tree_reference()?.id(),
+        tip.attach(repo).object()?.peel_to_tree()?.id,
+        "the tree of the last seen commit is kept reachable"
+    );
+
+    let missing = gix::hash::ObjectId::from_hex(b"f00df00df00df00df00df00df00df00df00df00d")?;
+    repo.reference(
+        index.config().seen_ref_name.as_str(),
+        missing,
+        PreviousValue::Any,
+        "simulate garbage-collected commit",
+    )?;
+    let (changes, to) = index.peek_changes()?;
+    assert_eq!(to, tip);
+    assert_eq!(
+        changes,
+        vec![Change::Squashed { from: missing, to }],
+        "the preserved tree is diffed instead, there are no changes, but the lost commit is reported"
+    );
+
+    index.last_seen_tree_reference()?.delete()?;
+    assert!(
+        matches!(
+            index.peek_changes(),
+            Err(Error::LastSeenObjectMissing { id, .. }) if id == missing
+        ),
+        "without preserved tree, we don't silently diff against the empty tree"
+    );
+    Ok(())
+}
+
+#[test]
+fn snapshot_branches_retain_order_across_squashes() -> crate::Result {
+    let (index, _tmp) = index_rw()?;
+    index.fetch_changes()?;
+    let previous_tip = index.last_seen_reference()?.id().detach();
+    let last_seen = index
+        .repository()
+        .rev_parse_single(format!("{}~1", index.config().seen_ref_name).as_str())?
+        .detach();
+    index.set_last_seen_reference(last_seen)?;
+
+    // Simulate a remote that squashed its history and archived the previous one in a snapshot branch.
+    let mut index = with_local_remote(index, "+refs/heads/main:refs/remotes/local/main")?;
+    index.set_fetch_snapshot_branches(true);
+    let repo = index.repository();
+    repo.reference(
+        "refs/heads/snapshot-2022-09-01",
+        previous_tip,
+        PreviousValue::Any,
+        "archive history before squash",
+    )?;
+    repo.reference(
+        "refs/heads/main",
+        repo.rev_parse_single("origin/squashed")?,
+        PreviousValue::Any,
+        "adjust to simulate remote with new squashed history",
+    )?;
+
+    let changes = index.fetch_changes_ordered()?;
+    let tip = index.last_seen_reference()?.id().detach();
+    assert!(
+        repo.find_reference("refs/remotes/local/snapshot-2022-09-01")
+            .is_ok(),
+        "snapshot branches are fetched as well"
+    );
+    assert_eq!(
+        changes.first().and_then(|c| c.squashed()),
+        Some((last_seen, tip)),
+        "the squash is still reported"
+    );
+    let (archived_changes, order) =
+        index.changes_between_ancestor_commits(last_seen, previous_tip)?;
+    assert_eq!(order, Order::AsInCratesIndex);
+    assert_eq!(
+        changes[1..=archived_changes.len()],
+        archived_changes[..],
+        "changes from the archived history come first, in order"
+    );
+    assert_eq!(
+        changes[archived_changes.len() + 1..]
+            .iter()
+            .map(|c| c.added().map(|v| (v.name.as_str(), v.version.as_str())))
+            .collect::<Vec<_>>(),
+        [Some(("git-repository", "1.0.0"))],
+        "the squash itself doesn't change anything, and the new history follows"
+    );
+
+    let (stitched_changes, order) = index.changes_between_ancestor_commits(last_seen, tip)?;
+    assert_eq!(
+        order,
+        Order::AsInCratesIndex,
+        "the snapshot branch connects both histories"
+    );
+    assert_eq!(stitched_changes[..], changes[1..]);
+    Ok(())
+}
+
+#[test]
+fn remote_head_is_followed_unless_branch_is_configured() -> crate::Result {
+    let (index, _tmp) = index_rw()?;
+    assert_eq!(
+        index.config().branch_name,
+        "main",
+        "detected from the clone"
+    );
+    assert!(index.config().follow_remote_head);
+    assert_eq!(
+        index.peek_changes()?.0.len(),
+        NUM_CHANGES_SINCE_EVER,
+        "the branch the remote HEAD points to is used"
+    );
+
+    let index = reopen(index, |builder| builder.branch_name("gone"))?;
+    assert!(!index.config().follow_remote_head);
+    assert!(
+        matches!(
+            index.peek_changes(),
+            Err(Error::BranchNotOnRemote { name, remote_head: Some(head) })
+                if name == "refs/heads/gone" && head == "main"
+        ),
+        "a configured branch that doesn't exist is reported along with the actual default branch"
+    );
+    Ok(())
+}
+
+#[test]
+fn builder_validates_configuration_before_cloning() -> crate::Result {
+    let tmp = TempDir::new()?;
+    let builder = || IndexBuilder::new(tmp.path()).clone_options(clone_options());
+    assert!(matches!(
+        builder()
+            .seen_ref_name("refs/remotes/origin/last-seen")
+            .build(gix::progress::Discard, &AtomicBool::default()),
+        Err(init::Error::SeenReferenceConflict { .. })
+    ));
+    assert!(matches!(
+        builder()
+            .seen_ref_name("refs/heads/last seen")
+            .build(gix::progress::Discard, &AtomicBool::default()),
+        Err(init::Error::InvalidReferenceName { .. })
+    ));
+    assert!(matches!(
+        builder()
+            .branch_name("main..")
+            .build(gix::progress::Discard, &AtomicBool::default()),
+        Err(init::Error::InvalidReferenceName { .. })
+    ));
+    assert_eq!(
+        std::fs::read_dir(tmp.path())?.count(),
+        0,
+        "nothing was cloned"
+    );
+
+    let tenant = "refs/heads/tenant-a";
+    let index = builder()
+        .branch_name("main")
+        .remote_name("origin")
+        .seen_ref_name(tenant)
+        .object_cache_size(0)
+        .github_fast_path(false)
+        .build(gix::progress::Discard, &AtomicBool::default())?;
+    assert_eq!(index.config().branch_name, "main");
+    assert!(
+        !index.config().follow_remote_head,
+        "the branch was configured"
+    );
+    assert_eq!(index.config().seen_ref_name, tenant);
+    assert!(!index.config().github_fast_path);
+
+    assert_eq!(index.fetch_changes()?.len(), NUM_CHANGES_SINCE_EVER);
+    assert_eq!(
+        index.last_seen_reference()?.name().as_bstr(),
+        tenant,
+        "the configured reference is used"
+    );
+    Ok(())
+}
+
+#[test]
+fn existing_clones_with_different_remote_url_are_rejected_or_repaired() -> crate::Result {
+    let (index, tmp) = index_rw()?;
+    index.fetch_changes()?;
+    drop(index);
+
+    let other_url = tmp.path().join("elsewhere").display().to_string();
+    let options = |on_url_mismatch| CloneOptions {
+        url: other_url.clone(),
+        on_url_mismatch,
+        ..Default::default()
+    };
+    let open = |options| {
+        Index::from_path_or_cloned_with_options(
+            tmp.path(),
+            gix::progress::Discard,
+            &AtomicBool::default(),
+            options,
+        )
+    };
+    assert!(
+        matches!(
+            open(options(UrlMismatch::Error)),
+            Err(init::Error::RemoteUrlMismatch { expected, actual: Some(_) }) if expected == other_url
+        ),
+        "by default, a clone of another repository is an error"
+    );
+    assert!(
+        matches!(
+            Index::from_path_or_cloned(tmp.path()),
+            Err(init::Error::RemoteUrlMismatch { .. })
+        ),
+        "it's not possible to accidentally use a clone of another registry"
+    );
+
+    let index = open(options(UrlMismatch::RewriteRemoteUrl))?;
+    let remote = index.repository().find_remote("origin")?;
+    assert_eq!(
+        remote
+            .url(gix::remote::Direction::Fetch)
+            .expect("set")
+            .to_bstring(),
+        other_url,
+        "the url was changed in the configuration"
+    );
+    assert!(
+        index.last_seen_reference().is_ok(),
+        "the repository itself wasn't touched"
+    );
+    drop(index);
+    open(options(UrlMismatch::Error)).expect("the url matches now");
+
+    let mut options = clone_options();
+    options.on_url_mismatch = UrlMismatch::Reclone;
+    let index = open(options)?;
+    assert!(
+        index.last_seen_reference().is_err(),
+        "the repository was cloned anew"
+    );
+    assert_eq!(index.peek_changes()?.0.len(), NUM_CHANGES_SINCE_EVER);
+    Ok(())
+}
+
+#[test]
+fn only_bare_repositories_are_deleted_to_clone_anew() -> crate::Result {
+    let tmp = TempDir::new().unwrap();
+    gix::init(tmp.path())?;
+    let work = tmp.path().join("work.txt");
+    std::fs::write(&work, b"precious")?;
+
+    let open = |on_url_mismatch, on_incomplete_clone| {
+        Index::from_path_or_cloned_with_options(
+            tmp.path(),
+            gix::progress::Discard,
+            &AtomicBool::default(),
+            CloneOptions {
+                on_url_mismatch,
+                on_incomplete_clone,
+                ..clone_options()
+            },
+        )
+    };
+    assert!(matches!(
+        open(UrlMismatch::Reclone, IncompleteClone::Error),
+        Err(init::Error::RefuseToRemove { path }) if path == tmp.path()
+    ));
+    assert!(work.is_file(), "the worktree is untouched");
+
+    let git_dir = tmp.path().join(".git");
+    let mut config = std::fs::OpenOptions::new()
+        .append(true)
+        .open(git_dir.join("config"))?;
+    writeln!(
+        config,
+        "[remote \"origin\"]\n\turl = {}",
+        clone_options().url
+    )?;
+    std::fs::write(git_dir.join("crates-index-diff-clone-in-progress"), b"")?;
+    assert!(matches!(
+        open(UrlMismatch::Error, IncompleteClone::Reclone),
+        Err(init::Error::RefuseToRemove { .. })
+    ));
+    assert!(work.is_file(), "the worktree is still untouched");
+    Ok(())
+}
+
+#[test]
+fn clones_from_relative_paths_can_be_reopened() -> crate::Result {
+    let base = fixture_dir()?.join("base");
+    let relative_url = base
+        .strip_prefix(std::env::current_dir()?)
+        .unwrap_or(&base)
+        .display()
+        .to_string();
+    assert!(base.is_relative() || relative_url != base.display().to_string());
+
+    let tmp = TempDir::new()?;
+    let open = || {
+        Index::from_path_or_cloned_with_options(
+            tmp.path(),
+            gix::progress::Discard,
+            &AtomicBool::default(),
+            CloneOptions {
+                url: relative_url.clone(),
+                ..Default::default()
+            },
+        )
+    };
+    let index = open()?;
+    let remote = index.repository().find_remote("origin")?;
+    assert_ne!(
+        remote
+            .url(gix::remote::Direction::Fetch)
+            .expect("set")
+            .to_bstring(),
+        relative_url,
+        "the path is made absolute when cloning"
+    );
+    drop(index);
+    open().expect("the relative path still refers to the same repository");
+    Ok(())
+}
+
+#[test]
+fn shallow_clones_are_deepened_on_demand() -> crate::Result {
+    let tmp = TempDir::new()?;
+    let index = IndexBuilder::new(tmp.path())
+        .clone_options(clone_options())
+        .shallow(gix::remote::fetch::Shallow::DepthAtRemote(1.try_into()?))
+        .build(gix::progress::Discard, &AtomicBool::default())?;
+    let repo = index.repository();
+    assert!(repo.is_shallow());
+    let tip = repo
+        .find_reference("refs/remotes/origin/main")?
+        .id()
+        .detach();
+
+    let base = gix::open(fixture_dir()?.join("base"))?;
+    let ancestor = base.rev_parse_single("main~10")?.detach();
+    assert!(
+        !repo.has_object(ancestor),
+        "the ancestor isn't part of the shallow clone"
+    );
+
+    assert!(
+        index
+            .changes_between_ancestor_commits_with_options(
+                ancestor,
+                tip,
+                gix::progress::Discard,
+                &AtomicBool::new(true),
+            )
+            .is_err(),
+        "deepening can be interrupted"
+    );
+    assert!(repo.is_shallow());
+
+    let (changes, order) = index.changes_between_ancestor_commits(ancestor, tip)?;
+    assert_eq!(
+        order,
+        Order::AsInCratesIndex,
+        "the repository was deepened to connect both commits"
+    );
+    assert!(
+        !repo.is_shallow(),
+        "the commit was unknown, so all history was fetched"
+    );
+    assert_eq!(
+        changes,
+        index_ro()?
+            .changes_between_ancestor_commits(ancestor, tip)?
+            .0,
+        "the result is the same as with a full clone"
+    );
+    Ok(())
+}
+
+#[test]
+fn shallow_clones_are_deepened_to_the_last_seen_commit() -> crate::Result {
+    let tmp = TempDir::new()?;
+    let index = IndexBuilder::new(tmp.path())
+        .clone_options(clone_options())
+        .shallow(gix::remote::fetch::Shallow::DepthAtRemote(20.try_into()?))
+        .build(gix::progress::Discard, &AtomicBool::default())?;
+    let repo = index.repository();
+    let tip = repo.rev_parse_single("origin/main")?.detach();
+    let last_seen = repo.rev_parse_single("origin/main~10")?.detach();
+    index.set_last_seen_reference(last_seen)?;
+
+    // Simulate a shallow boundary between the last-seen commit and the tip, as if it was moved by a depth-limited fetch.
+    let mut shallow = std::fs::read_to_string(repo.shallow_file())?;
+    shallow.push_str(&format!("{}\n", repo.rev_parse_single("origin/main~5")?));
+    std::fs::write(repo.shallow_file(), shallow)?;
+    assert!(
+        !index.changes_between_commits(last_seen, tip)?.is_empty(),
+        "the trees can be diffed as all objects are present"
+    );
+
+    let (changes, order) = index.changes_between_ancestor_commits(last_seen, tip)?;
+    assert_eq!(
+        order,
+        Order::AsInCratesIndex,
+        "the shallow boundary was moved back to include the last-seen commit"
+    );
+    assert_eq!(
+        changes,
+        index_ro()?
+            .changes_between_ancestor_commits(last_seen, tip)?
+            .0
+    );
+    assert!(
+        index
+            .fetch_changes()?
+            .iter()
+            .all(|change| change.squashed().is_none()),
+        "the last seen commit is known to be an ancestor"
+    );
+    Ok(())
+}
+
+#[test]
+fn incomplete_clones_are_detected_and_resumed_or_recloned() -> crate::Result {
+    let (index, tmp) = index_rw()?;
+    let git_dir = index.repository().git_dir().to_owned();
+    drop(index);
+    let marker = git_dir.join("crates-index-diff-clone-in-progress");
+    assert!(!marker.exists(), "removed after the clone succeeded");
+
+    let remove_refs = || {
+        std::fs::remove_file(git_dir.join("packed-refs")).ok();
+        std::fs::remove_dir_all(git_dir.join("refs/remotes")).ok();
+    };
+    let make_incomplete = || {
+        remove_refs();
+        std::fs::write(&marker, b"").unwrap();
+    };
+    let open = |on_incomplete_clone| {
+        Index::from_path_or_cloned_with_options(
+            tmp.path(),
+            gix::progress::Discard,
+            &AtomicBool::default(),
+            CloneOptions {
+                on_incomplete_clone,
+                ..clone_options()
+            },
+        )
+    };
+    remove_refs();
+    open(IncompleteClone::Error).expect(
+        "repositories we didn't start cloning are complete, even without remote tracking branches",
+    );
+
+    make_incomplete();
+    assert!(matches!(
+        open(IncompleteClone::Error),
+        Err(init::Error::IncompleteClone { path }) if path == tmp.path()
+    ));
+
+    for policy in [IncompleteClone::Resume, IncompleteClone::Reclone] {
+        make_incomplete();
+        let index = open(policy)?;
+        assert!(!marker.exists(), "{policy:?}: the clone is complete now");
+        assert_eq!(
+            index.peek_changes()?.0.len(),
+            NUM_CHANGES_SINCE_EVER,
+            "{policy:?}: all changes are visible"
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn fetches_are_serialized_across_instances() -> crate::Result {
+    let (mut index, tmp) = index_rw()?;
+    let lock_path = index.repository().git_dir().join("crates-index-diff.lock");
+    let lock = std::fs::File::create(&lock_path)?;
+    lock.lock()?;
+    assert!(
+        index
+            .try_fetch_changes_with_options(
+                gix::progress::Discard,
+                &AtomicBool::default(),
+                Order::ImplementationDefined
+            )?
+            .is_none(),
+        "another process holds the lock"
+    );
+    index.set_lock_timeout(Duration::from_millis(50));
+    assert!(matches!(index.fetch_changes(), Err(Error::Lock { .. })));
+    assert!(index.last_seen_reference().is_err(), "nothing was changed");
+    drop(lock);
+
+    let num_changes = std::thread::scope(|s| {
+        let handles: Vec<_> = (0..2)
+            .map(|_| {
+                s.spawn(|| {
+                    let index = Index::from_path_or_cloned_with_options(
+                        tmp.path(),
+                        gix::progress::Discard,
+                        &AtomicBool::default(),
+                        clone_options(),
+                    )
+                    .expect("repository exists");
+                    index.fetch_changes().expect("lock is obtained").len()
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|h| h.join().expect("no panic"))
+            .collect::<Vec<_>>()
+    });
+    assert_eq!(
+        num_changes.iter().sum::<usize>(),
+        NUM_CHANGES_SINCE_EVER,
+        "each change is observed exactly once"
+    );
+    Ok(())
+}
+
+#[test]
+fn lock_files_left_behind_by_crashed_processes_are_ignored() -> crate::Result {
+    let (mut index, _tmp) = index_rw()?;
+    std::fs::write(
+        index.repository().git_dir().join("crates-index-diff.lock"),
+        "",
+    )?;
+    index.set_lock_timeout(Duration::from_millis(50));
+    assert_eq!(
+        index.fetch_changes()?.len(),
+        NUM_CHANGES_SINCE_EVER,
+        "only a process that is alive can hold the lock"
+    );
+    Ok(())
+}
+
+#[test]
+fn thread_safe_index_can_be_shared_across_threads() -> crate::Result {
+    fn assert_send_sync<T: Send + Sync + Clone>(_: &T) {}
+    let index = IndexBuilder::new(fixture_dir()?.join("clone"))
+        .clone_options(clone_options())
+        .object_cache_size(1024 * 1024)
+        .build(gix::progress::Discard, &AtomicBool::default())?;
+    let expected = index.changes("@~3", "@")?;
+    let shared = index.into_sync();
+    assert_send_sync(&shared);
+
+    std::thread::scope(|s| {
+        for _ in 0..2 {
+            let shared = shared.clone();
+            let expected = &expected;
+            s.spawn(move || {
+                let index = shared.to_thread_local();
+                assert_eq!(
+                    index.config().branch_name,
+                    "main",
+                    "configuration is retained"
+                );
+                assert_eq!(
+                    index.config().object_cache_size,
+                    Some(1024 * 1024),
+                    "the object cache is sized as configured"
+                );
+                assert_eq!(
+                    &index.changes("@~3", "@").expect("valid revspecs"),
+                    expected
+                );
+            });
+        }
+    });
+    Ok(())
+}
+
+#[cfg(feature = "async")]
+#[test]
+fn async_clone_peek_and_fetch() -> crate::Result {
+    let tmp = TempDir::new()?;
+    let rt = tokio::runtime::Builder::new_current_thread().build()?;
+    let index = rt.block_on(ThreadSafeIndex::from_path_or_cloned_async(
+        tmp.path(),
+        clone_options(),
+    ))?;
+
+    let (changes, to) = rt.block_on(index.peek_changes_async(Order::ImplementationDefined))?;
+    assert_eq!(changes.len(), NUM_CHANGES_SINCE_EVER);
+    assert!(
+        index.to_thread_local().last_seen_reference().is_err(),
+        "peeking doesn't change the last-seen reference"
+    );
+
+    assert_eq!(
+        rt.block_on(index.fetch_changes_async(Order::AsInCratesIndex))?
+            .len(),
+        NUM_CHANGES_SINCE_EVER
+    );
+    assert_eq!(index.to_thread_local().last_seen_reference()?.id(), to);
+    assert!(
+        rt.block_on(index.fetch_changes_async(Order::AsInCratesIndex))?
+            .is_empty(),
+        "nothing changed since the last fetch"
+    );
+    Ok(())
+}
+
+#[test]
+fn fetch_only_and_pending_commits() -> crate::Result {
+    let (index, _tmp) = index_rw()?;
+    let repo = index.repository();
+    let tip = index.fetch_only()?;
+    assert_eq!(tip, repo.find_reference("refs/remotes/origin/main")?.id());
+    assert!(
+        index.last_seen_reference().is_err(),
+        "only the remote tracking branch is updated"
+    );
+
+    let pending = index.pending_commits()?;
+    assert_eq!(pending.tip, tip);
+    assert_eq!(
+        pending.count,
+        repo.find_object(tip)?
+            .into_commit()
+            .ancestors()
+            .all()?
+            .count(),
+        "without a last-seen commit, the whole history is pending"
+    );
+
+    index.set_last_seen_reference(repo.rev_parse_single("origin/main~5")?.detach())?;
+    let pending = index.pending_commits()?;
+    assert_eq!(pending.count, 5);
+    let time_span = pending.time_span.expect("there are pending commits");
+    assert!(time_span.start() <= time_span.end());
+
+    index.fetch_changes()?;
+    let pending = index.pending_commits()?;
+    assert_eq!(pending.count, 0);
+    assert_eq!(pending.time_span, None);
+
+    let index = reopen(index, |builder| builder.branch_name("gone"))?;
+    assert!(matches!(
+        index.pending_commits(),
+        Err(Error::NoTrackingBranch { name }) if name == "refs/heads/gone"
+    ));
+    Ok(())
+}
+
+#[test]
+fn offline_peek_uses_the_tracking_branch() -> crate::Result {
+    let (index, tmp) = index_rw()?;
+    drop(index);
+    let mut index = Index::from_path_or_cloned_with_options(
+        tmp.path(),
+        gix::progress::Discard,
+        &AtomicBool::default(),
+        CloneOptions {
+            url: tmp.path().join("unreachable").display().to_string(),
+            on_url_mismatch: UrlMismatch::RewriteRemoteUrl,
+            ..Default::default()
+        },
+    )?;
+    assert!(
+        index.peek_changes().is_err(),
+        "the remote can't be reached anymore"
+    );
+
+    index.set_offline(true);
+    let (changes, to) = index.peek_changes()?;
+    assert_eq!(changes.len(), NUM_CHANGES_SINCE_EVER);
+    assert_eq!(
+        to,
+        index
+            .repository()
+            .find_reference("refs/remotes/origin/main")?
+            .id()
+    );
+    assert_eq!(index.fetch_changes()?.len(), NUM_CHANGES_SINCE_EVER);
+    assert!(index.fetch_changes()?.is_empty());
+
+    let index = reopen(index, |builder| {
+        builder
+            .url(tmp.path().join("unreachable").display().to_string())
+            .offline(true)
+            .branch_name("gone")
+    })?;
+    assert!(
+        matches!(
+            index.peek_changes(),
+            Err(Error::NoTrackingBranch { name }) if name == "refs/heads/gone"
+        ),
+        "without tracking branch there is nothing to diff against"
+    );
+    Ok(())
+}
+
+#[test]
+fn a_changed_remote_head_is_remembered_for_all_operations() -> crate::Result {
+    let (upstream, upstream_tmp) = index_rw()?;
+    let repo = upstream.repository();
+    let old_tip = repo.rev_parse_single("main~5")?.detach();
+    let tip = repo.rev_parse_single("main")?.detach();
+    repo.reference(
+        "refs/heads/trunk",
+        tip,
+        PreviousValue::Any,
+        "new default branch",
+    )?;
+    repo.reference(
+        "refs/heads/main",
+        old_tip,
+        PreviousValue::Any,
+        "old default branch",
+    )?;
+    drop(upstream);
+
+    let tmp = TempDir::new().unwrap();
+    let index = Index::from_path_or_cloned_with_options(
+        tmp.path(),
+        gix::progress::Discard,
+        &AtomicBool::default(),
+        CloneOptions {
+            url: upstream_tmp.path().display().to_string(),
+            ..Default::default()
+        },
+    )?;
+    assert_eq!(index.branch_name(), "main");
+    assert_eq!(index.fetch_only()?, old_tip);
+    index.set_last_seen_reference(old_tip)?;
+
+    std::fs::write(upstream_tmp.path().join("HEAD"), "ref: refs/heads/trunk\n")?;
+    assert_eq!(
+        index.fetch_only()?,
+        tip,
+        "the new default branch is fetched"
+    );
+    assert_eq!(index.branch_name(), "trunk");
+    let pending = index.pending_commits()?;
+    assert_eq!(
+        (pending.tip, pending.count),
+        (tip, 5),
+        "pending commits are counted on the new default branch"
+    );
+    drop(index);
+
+    let mut index = Index::from_path_or_cloned_with_options(
+        tmp.path(),
+        gix::progress::Discard,
+        &AtomicBool::default(),
+        CloneOptions {
+            url: upstream_tmp.path().display().to_string(),
+            ..Default::default()
+        },
+    )?;
+    assert_eq!(index.branch_name(), "trunk", "the remote HEAD is persisted");
+    index.set_offline(true);
+    assert_eq!(index.peek_changes()?.1, tip);
+    Ok(())
+}
+
+#[test]
+fn bundles_can_be_exported_and_imported_for_offline_use() -> crate::Result {
+    let (upstream, upstream_tmp) = index_rw()?;
+    let repo = upstream.repository();
+    let old_tip = repo.rev_parse_single("main~5")?.detach();
+    let tip = repo.rev_parse_single("main")?.detach();
+    repo.reference(
+        "refs/heads/main",
+        old_tip,
+        PreviousValue::Any,
+        "make upstream lag behind",
+    )?;
+    drop(upstream);
+
+    let tmp = TempDir::new().unwrap();
+    let mut receiver = Index::from_path_or_cloned_with_options(
+        tmp.path(),
+        gix::progress::Discard,
+        &AtomicBool::default(),
+        CloneOptions {
+            url: upstream_tmp.path().display().to_string(),
+            ..Default::default()
+        },
+    )?;
+    receiver.set_offline(true);
+    receiver.fetch_changes()?;
+    assert!(
+        !receiver.repository().has_object(tip),
+        "the receiver only has what upstream had"
+    );
+
+    let (mut sender, sender_tmp) = index_rw()?;
+    sender.set_last_seen_reference(old_tip)?;
+    let bundle = sender_tmp.path().join("update.bundle");
+    assert_eq!(sender.export_bundle(&bundle)?, tip);
+
+    assert_eq!(receiver.import_bundle(&bundle)?, tip);
+    assert!(receiver.repository().has_object(tip));
+    let (changes, to) = receiver.peek_changes_ordered()?;
+    assert_eq!(to, tip);
+    assert!(!changes.is_empty());
+    assert_eq!(
+        (changes, to),
+        sender.peek_changes_ordered()?,
+        "imported changes are the same as fetched ones"
+    );
+    assert_eq!(
+        receiver.import_bundle(&bundle)?,
+        tip,
+        "importing again is a no-op"
+    );
+
+    sender.set_offline(true);
+    sender.fetch_changes()?;
+    assert!(matches!(
+        receiver.import_bundle(sender_tmp.path().join("config")),
+        Err(bundle::Error::InvalidHeader { .. })
+    ));
+    sender.export_bundle(&bundle)?;
+    let fresh_tmp = TempDir::new().unwrap();
+    let mut fresh = Index::from_path_or_cloned_with_options(
+        fresh_tmp.path(),
+        gix::progress::Discard,
+        &AtomicBool::default(),
+        CloneOptions {
+            url: upstream_tmp.path().display().to_string(),
+            ..Default::default()
+        },
+    )?;
+    fresh.set_offline(true);
+    assert!(
+        matches!(
+            fresh.import_bundle(&bundle),
+            Err(bundle::Error::MissingPrerequisites { ids }) if ids == [tip]
+        ),
+        "the bundle builds on a commit the receiver doesn't have"
+    );
+    Ok(())
+}
+
+#[test]
+fn transient_fetch_failures_are_retried() -> crate::Result {
+    let server = FlakyGitServer::new(fixture_dir()?.join("base"))?;
+    let tmp = TempDir::new().unwrap();
+    let mut index = IndexBuilder::new(tmp.path())
+        .url(&server.url)
+        .branch_name("main")
+        .build(gix::progress::Discard, &AtomicBool::default())?;
+
+    server.drop_connections(1);
+    let err = index.peek_changes().unwrap_err();
+    assert!(
+        err.is_transient(),
+        "dropped connections are transient: {err:?}"
+    );
+
+    index.set_retry_policy(RetryPolicy {
+        max_attempts: 3,
+        initial_backoff: Duration::from_millis(10),
+        ..Default::default()
+    });
+    server.drop_connections(2);
+    let connections = server.connections();
+    assert_eq!(index.peek_changes()?.0.len(), NUM_CHANGES_SINCE_EVER);
+    assert_eq!(server.connections() - connections, 3);
+
+    server.drop_connections(3);
+    assert!(
+        index.peek_changes().is_err_and(|err| err.is_transient()),
+        "it gives up after the last attempt"
+    );
+
+    index.set_retry_policy(RetryPolicy {
+        max_attempts: 2,
+        initial_backoff: Duration::from_secs(30),
+        ..Default::default()
+    });
+    server.drop_connections(1);
+    let should_interrupt = AtomicBool::default();
+    let start = std::time::Instant::now();
+    let err = std::thread::scope(|scope| {
+        scope.spawn(|| {
+            std::thread::sleep(Duration::from_millis(100));
+            should_interrupt.store(true, Ordering::Relaxed);
+        });
+        index
+            .peek_changes_with_options(
+                gix::progress::Discard,
+                &should_interrupt,
+                Order::ImplementationDefined,
+            )
+            .unwrap_err()
+    });
+    assert!(err.is_transient(), "the last error is returned");
+    assert!(
+        start.elapsed() < Duration::from_secs(10),
+        "waiting for the next attempt stops when interrupted"
+    );
+
+    let retry_policy = index.config().retry_policy;
+    let index = reopen(index, |builder| {
+        builder
+            .url(&server.url)
+            .retry_policy(retry_policy)
+            .branch_name("gone")
+    })?;
+    let connections = server.connections();
+    let err = index.peek_changes().unwrap_err();
+    assert!(matches!(err, Error::BranchNotOnRemote { .. }));
+    assert!(!err.is_transient());
+    assert_eq!(
+        server.connections() - connections,
+        1,
+        "other errors aren't retried"
+    );
+
+    let policy = RetryPolicy {
+        max_attempts: 5,
+        initial_backoff: Duration::from_millis(100),
+        max_backoff: Duration::from_millis(300),
+        jitter: 0.0,
+    };
+    assert_eq!(policy.backoff(1), Duration::from_millis(100));
+    assert_eq!(policy.backoff(2), Duration::from_millis(200));
+    assert_eq!(policy.backoff(3), Duration::from_millis(300), "capped");
+    let policy = RetryPolicy {
+        jitter: 0.5,
+        ..policy
+    };
+    for attempt in 1..5 {
+        let backoff = policy.backoff(attempt);
+        assert!(backoff >= Duration::from_millis(50) && backoff <= Duration::from_millis(300));
+    }
+    Ok(())
+}
+
+#[test]
+fn changes_can_be_queried_by_commit_time() -> crate::Result {
+    let (index, _tmp) = index_rw()?;
+    index.fetch_only()?;
+    // All commits of the fixture were made at the same time.
+    let commit_time = std::time::UNIX_EPOCH + Duration::from_secs(946771200);
+    let second = Duration::from_secs(1);
+
+    let repo = index.repository();
+    let tip = repo.rev_parse_single("origin/main")?.detach();
+    let root = repo
+        .find_commit(tip)?
+        .ancestors()
+        .first_parent_only()
+        .all()?
+        .last()
+        .expect("history isn't empty")?
+        .id;
+    let (mut expected, order) = index.changes_between_ancestor_commits(root, tip)?;
+    assert_eq!(order, Order::AsInCratesIndex);
+    expected.splice(
+        0..0,
+        index.changes_between_commits(gix::hash::ObjectId::empty_tree(repo.object_hash()), root)?,
+    );
+
+    let changes = index.changes_since(commit_time)?;
+    assert_eq!(
+        changes.len(),
+        expected.len(),
+        "each commit is looked at separately"
+    );
+    assert_eq!(
+        index
+            .changes_between_times(commit_time, commit_time + second)?
+            .len(),
+        changes.len()
+    );
+    assert!(
+        index.changes_since(commit_time + second)?.is_empty(),
+        "no commits were made afterwards"
+    );
+    assert!(
+        index
+            .changes_between_times(commit_time - second, commit_time)?
+            .is_empty(),
+        "the end of the time range is exclusive"
+    );
+
+    let tmp = TempDir::new()?;
+    let mut shallow = IndexBuilder::new(tmp.path())
+        .clone_options(clone_options())
+        .shallow(gix::remote::fetch::Shallow::DepthAtRemote(1.try_into()?))
+        .build(gix::progress::Discard, &AtomicBool::default())?;
+    shallow.set_offline(true);
+    assert!(
+        matches!(
+            shallow.changes_since(commit_time),
+            Err(Error::StartOfTimeRangeMissing { oldest }) if oldest == tip
+        ),
+        "the shallow history can't be deepened, and isn't treated as if it started with the tip"
+    );
+    Ok(())
+}
+
+#[test]
+fn supply_chain_analyzer_reports_new_dependencies_with_their_age() -> crate::Result {
+    let index = index_ro()?;
+    let repo = index.repository();
+    let tip = repo.rev_parse_single("main")?.detach();
+    let changes_of = |subject: &str| -> crate::Result<Vec<Change>> {
+        let commit = repo.rev_parse_single(format!("@^{{/{subject}}}").as_str())?;
+        Ok(index.changes_in_range(format!("{commit}^!"))?)
+    };
+    let time_of = |subject: &str| -> crate::Result<std::time::SystemTime> {
+        let commit = repo
+            .rev_parse_single(format!("@^{{/{subject}}}").as_str())?
+            .object()?
+            .into_commit();
+        Ok(std::time::UNIX_EPOCH + Duration::from_secs(commit.committer()?.seconds() as u64))
+    };
+    let mut analyzer = index.supply_chain_analyzer(tip)?;
+
+    assert_eq!(
+        analyzer.new_dependencies(&changes_of("Updating crate `git-refspec#0.0.0`")?)?,
+        [],
+        "the first version of a crate has nothing to compare to"
+    );
+
+    let introduced =
+        analyzer.new_dependencies(&changes_of("Updating crate `git-refspec#0.1.0`")?)?;
+    let oldest = time_of("initial commit")?;
+    assert_eq!(
+        introduced
+            .iter()
+            .map(|d| (
+                d.crate_name.as_str(),
+                d.crate_version.as_str(),
+                d.dependency.name.as_str(),
+                d.dependency_age
+            ))
+            .collect::<Vec<_>>(),
+        [
+            (
+                "git-refspec",
+                "0.1.0",
+                "git-hash",
+                DependencyAge::PublishedAtOrBefore(oldest)
+            ),
+            (
+                "git-refspec",
+                "0.1.0",
+                "git-revision",
+                DependencyAge::PublishedAtOrBefore(oldest)
+            ),
+            (
+                "git-refspec",
+                "0.1.0",
+                "git-validate",
+                DependencyAge::PublishedAtOrBefore(oldest)
+            ),
+            ("git-refspec", "0.1.0", "smallvec", DependencyAge::Unknown),
+        ],
+        "crates of the initial commit might be older, and those that aren't in the index are of unknown age"
+    );
+
+    let introduced =
+        analyzer.new_dependencies(&changes_of("Updating crate `git-repository#0.22.0`")?)?;
+    let refspec = introduced
+        .iter()
+        .find(|d| d.dependency.name == "git-refspec")
+        .expect("git-refspec is a new dependency");
+    assert_eq!(
+        refspec.dependency_age,
+        DependencyAge::Published(time_of("Updating crate `git-refspec#0.0.0`")?),
+        "the crate was added in the available history"
+    );
+    assert!(
+        introduced
+            .iter()
+            .all(|d| d.dependency.name != "git-features"),
+        "dependencies that earlier versions had aren't reported"
+    );
+    for name in ["ä", "gït-hash", ""] {
+        assert_eq!(
+            analyzer.dependency_age(name)?,
+            DependencyAge::Unknown,
+            "{name:?} can't be the name of a crate"
+        );
+    }
+    Ok(())
+}
+
 fn index_ro() -> crate::Result<Index> {
     let dir = fixture_dir()?;
-    Ok(Index::from_path_or_cloned(dir.join("clone"))?)
+    Ok(Index::from_path_or_cloned_with_options(
+        dir.join("clone"),
+        gix::progress::Discard,
+        &AtomicBool::default(),
+        clone_options(),
+    )?)
 }
 
 fn index_rw() -> crate::Result<(Index, TempDir)> {
     let tmp = TempDir::new().unwrap();
-    let mut index = Index::from_path_or_cloned_with_options(
+    let index = Index::from_path_or_cloned_with_options(
         tmp.path(),
         gix::progress::Discard,
         &AtomicBool::default(),
         clone_options(),
     )?;
-    index.branch_name = "main";
     Ok((index, tmp))
 }
 
+/// Drop `index` and open its repository again with the settings of `builder`.
+fn reopen(
+    index: Index,
+    builder: impl FnOnce(IndexBuilder) -> IndexBuilder,
+) -> crate::Result<Index> {
+    let git_dir = index.repository().git_dir().to_owned();
+    drop(index);
+    Ok(
+        builder(IndexBuilder::new(git_dir).clone_options(clone_options()))
+            .build(gix::progress::Discard, &AtomicBool::default())?,
+    )
+}
+
+/// Add a remote named `local` that fetches from the repository of `index` itself with `refspec`,
+/// and open it again to fetch from that remote.
+fn with_local_remote(index: Index, refspec: &str) -> crate::Result<Index> {
+    let git_dir = index.repository().git_dir().to_owned();
+    let mut config = std::fs::OpenOptions::new()
+        .append(true)
+        .open(git_dir.join("config"))?;
+    writeln!(
+        config,
+        "[remote \"local\"]\n\turl = {}\n\tfetch = {refspec}",
+        git_dir.display()
+    )?;
+    reopen(index, |builder| {
+        builder
+            .url(git_dir.display().to_string())
+            .remote_name("local")
+    })
+}
+
 fn fixture_dir() -> crate::Result<PathBuf> {
     gix_testtools::scripted_fixture_read_only_with_args(
         "make-index-from-parts.sh",
@@ -187,8 +1318,97 @@ fn fixture_dir() -> crate::Result<PathBuf> {
     )
 }
 
-fn clone_options() -> crates_index_diff::index::CloneOptions {
-    crates_index_diff::index::CloneOptions {
+fn clone_options() -> CloneOptions {
+    CloneOptions {
         url: fixture_dir().unwrap().join("base").display().to_string(),
+        ..Default::default()
+    }
+}
+
+/// A stand-in for a git server that serves `dir` via the `git://` protocol, but can be told to drop connections.
+struct FlakyGitServer {
+    url: String,
+    drops: Arc<AtomicUsize>,
+    connections: Arc<AtomicUsize>,
+}
+
+impl FlakyGitServer {
+    fn new(dir: PathBuf) -> crate::Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let url = format!("git://{}/index", listener.local_addr()?);
+        let drops = Arc::new(AtomicUsize::default());
+        let connections = Arc::new(AtomicUsize::default());
+        std::thread::spawn({
+            let drops = drops.clone();
+            let connections = connections.clone();
+            move || {
+                for stream in listener.incoming() {
+                    let Ok(stream) = stream else { continue };
+                    connections.fetch_add(1, Ordering::SeqCst);
+                    if drops
+                        .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+                        .is_ok()
+                    {
+                        continue;
+                    }
+                    let dir = dir.clone();
+                    std::thread::spawn(move || Self::serve(stream, &dir));
+                }
+            }
+        });
+        Ok(FlakyGitServer {
+            url,
+            drops,
+            connections,
+        })
+    }
+
+    /// Drop the next `count` connections right after accepting them.
+    fn drop_connections(&self, count: usize) {
+        self.drops.store(count, Ordering::SeqCst);
+    }
+
+    /// The amount of connections accepted so far.
+    fn connections(&self) -> usize {
+        self.connections.load(Ordering::SeqCst)
+    }
+
+    /// Read the initial request and let `git upload-pack` handle the rest of the conversation.
+    fn serve(mut stream: TcpStream, dir: &std::path::Path) -> std::io::Result<()> {
+        let mut len = [0; 4];
+        stream.read_exact(&mut len)?;
+        let len = usize::from_str_radix(std::str::from_utf8(&len).expect("hex"), 16).expect("hex");
+        let mut request = vec![0; len - 4];
+        stream.read_exact(&mut request)?;
+        let protocol = request
+            .split(|b| *b == 0)
+            .find(|param| param.starts_with(b"version="))
+            .map(|param| String::from_utf8_lossy(param).into_owned())
+            .unwrap_or_default();
+
+        let mut child = Command::new("git")
+            .args(["upload-pack", "--strict"])
+            .arg(dir)
+            .env("GIT_PROTOCOL", protocol)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let mut stdin = child.stdin.take().expect("piped");
+        let mut input = stream.try_clone()?;
+        std::thread::spawn(move || Self::forward(&mut input, &mut stdin));
+        Self::forward(child.stdout.as_mut().expect("piped"), &mut stream)?;
+        child.wait()?;
+        Ok(())
+    }
+
+    /// Like `std::io::copy()`, which was seen to stall when splicing between sockets and pipes.
+    fn forward(from: &mut impl Read, to: &mut impl Write) -> std::io::Result<()> {
+        let mut buf = [0; 64 * 1024];
+        loop {
+            match from.read(&mut buf)? {
+                0 => return Ok(()),
+                n => to.write_all(&buf[..n])?,
+            }
+        }
     }
 }
@@ -0,0 +1,142 @@
+use crates_index_diff::lockfile::{Finding, LockedPackage, Lockfile};
+use crates_index_diff::{Change, CrateVersion};
+use semver::Version;
+
+const CARGO_LOCK: &str = r#"# This file is automatically @generated by Cargo.
+# It is not intended for manual editing.
+version = 3
+
+[[package]]
+name = "app"
+version = "0.1.0"
+dependencies = [
+ "bstr",
+ "git-hash 0.9.7",
+ "git-hash 0.10.0",
+ "forked",
+]
+
+[[package]]
+name = "bstr"
+version = "0.2.17"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+checksum = "ba3569f383e8f1598449f1a423e72e99569137b47740b1da11ef19af3d5c3223"
+dependencies = [
+ "memchr",
+]
+
+[[package]]
+name = "forked"
+version = "1.0.0"
+source = "git+https://github.com/example/forked#0123456789abcdef0123456789abcdef01234567"
+
+[[package]]
+name = "git-hash"
+version = "0.9.7"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+
+[[package]]
+name = "git-hash"
+version = "0.10.0"
+source = "sparse+https://index.crates.io/"
+"#;
+
+#[test]
+fn parse() -> crate::Result {
+    let lockfile = Lockfile::parse(CARGO_LOCK)?;
+    assert_eq!(
+        lockfile
+            .packages
+            .iter()
+            .map(|p| (
+                p.name.as_str(),
+                p.version.to_string(),
+                p.is_from_crates_io()
+            ))
+            .collect::<Vec<_>>(),
+        [
+            ("app", "0.1.0".into(), false),
+            ("bstr", "0.2.17".into(), true),
+            ("forked", "1.0.0".into(), false),
+            ("git-hash", "0.9.7".into(), true),
+            ("git-hash", "0.10.0".into(), true),
+        ]
+    );
+    assert!(Lockfile::parse("[[package]]\nname = \"a\"\n").is_err());
+    Ok(())
+}
+
+#[test]
+fn compatible_updates() -> crate::Result {
+    let locked = |version: &str| -> crate::Result<LockedPackage> {
+        Ok(LockedPackage {
+            name: "a".into(),
+            version: Version::parse(version)?,
+            source: None,
+        })
+    };
+    for (locked_version, version, expected) in [
+        ("1.2.3", "1.2.4", true),
+        ("1.2.3", "1.9.0", true),
+        ("1.2.3", "1.2.3", false),
+        ("1.2.3", "1.2.2", false),
+        ("1.2.3", "2.0.0", false),
+        ("0.2.3", "0.2.9", true),
+        ("0.2.3", "0.3.0", false),
+        ("0.0.3", "0.0.4", false),
+    ] {
+        assert_eq!(
+            locked(locked_version)?.is_compatible_update(&Version::parse(version)?),
+            expected,
+            "{locked_version} -> {version}"
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn check_changes() -> crate::Result {
+    let lockfile = Lockfile::parse(CARGO_LOCK)?;
+    let version = |name: &str, version: &str, yanked: bool| CrateVersion {
+        name: name.into(),
+        version: version.into(),
+        yanked,
+        ..Default::default()
+    };
+    let added = |version: CrateVersion| Change::Added {
+        version,
+        dependency_changes: None,
+        feature_changes: None,
+    };
+    let changes = [
+        Change::Yanked(version("git-hash", "0.9.7", true)),
+        Change::Yanked(version("git-hash", "0.9.6", true)),
+        added(version("git-hash", "0.9.8", false)),
+        added(version("git-hash", "0.10.1", false)),
+        Change::AddedAndYanked(version("bstr", "0.2.18", true)),
+        added(version("bstr", "1.0.0", false)),
+        added(version("forked", "1.0.1", false)),
+        added(version("app", "0.1.1", false)),
+        Change::VersionDeleted(version("bstr", "0.2.17", false)),
+    ];
+
+    let packages = &lockfile.packages;
+    assert_eq!(
+        lockfile.check(&changes),
+        [
+            Finding::Yanked {
+                locked: &packages[3]
+            },
+            Finding::CompatibleUpdate {
+                locked: &packages[3],
+                version: Version::new(0, 9, 8)
+            },
+            Finding::CompatibleUpdate {
+                locked: &packages[4],
+                version: Version::new(0, 10, 1)
+            },
+        ],
+        "only packages from crates.io are considered, and yanked versions aren't updates"
+    );
+    Ok(())
+}
@@ -112,10 +112,21 @@ pub fn baseline(mode: Step) -> Result<(), Box<dyn std::error::Error + Send + Syn
                     let num_changes = changes.len();
                     for change in changes {
                         match change {
-                            Added(v) | AddedAndYanked(v) => {
+                            Added { version: v, .. } | AddedAndYanked(v) => {
                                 // found a new crate, add it to the index
                                 versions.insert(v.checksum.to_owned(), v.yanked);
                             }
+                            CrateCreated {
+                                versions: added, ..
+                            } => {
+                                // found a new crate with all of its versions
+                                for added_version in added {
+                                    versions.insert(
+                                        added_version.checksum.to_owned(),
+                                        added_version.yanked,
+                                    );
+                                }
+                            }
                             Unyanked(v) | Yanked(v) => {
                                 *versions
                                     .get_mut(&v.checksum)
@@ -133,6 +144,9 @@ pub fn baseline(mode: Step) -> Result<(), Box<dyn std::error::Error + Send + Syn
                             VersionDeleted(v) => {
                                 versions.remove(&v.checksum);
                             }
+                            Squashed { .. } => {
+                                unreachable!("only fetching can detect squashed history")
+                            }
                         }
                     }
                     let elapsed = start.elapsed().as_secs_f32();
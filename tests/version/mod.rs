@@ -25,7 +25,7 @@ fn parse_crate_version() {
             version: "1.0.0".into(),
             dependencies: Vec::new(),
             features: HashMap::new(),
-            checksum: Default::default()
+            checksum: Default::default(),
         }
     );
 }
@@ -100,7 +100,162 @@ fn parse_crate_version_with_dependencies() {
                 package: Some("dep_package".into())
             }],
             features: HashMap::new(),
-            checksum: Default::default()
+            checksum: Default::default(),
         }
     );
 }
+
+#[test]
+fn dependency_changes_since_previous_version() {
+    let dep = |name: &str, req: &str, kind: DependencyKind| Dependency {
+        name: name.into(),
+        required_version: req.into(),
+        features: Vec::new(),
+        optional: false,
+        default_features: true,
+        target: None,
+        kind: Some(kind),
+        package: None,
+    };
+    let version = |dependencies: Vec<Dependency>| CrateVersion {
+        name: "test".into(),
+        dependencies,
+        ..Default::default()
+    };
+    let previous = version(vec![
+        dep("serde", "^1.0.100", DependencyKind::Normal),
+        dep("serde", "^1.0.100", DependencyKind::Dev),
+        dep("log", "^0.4", DependencyKind::Normal),
+        dep("cc", "^1", DependencyKind::Normal),
+        dep("unchanged", "^1", DependencyKind::Normal),
+    ]);
+    let current = version(vec![
+        dep("serde", "^1.0.150", DependencyKind::Normal),
+        dep("serde", "^1.0.100", DependencyKind::Dev),
+        dep("cc", "^1", DependencyKind::Build),
+        dep("unchanged", "^1", DependencyKind::Normal),
+        dep("anyhow", "1", DependencyKind::Normal),
+    ]);
+
+    let changes = current.dependency_changes_since(&previous);
+    assert_eq!(
+        changes,
+        [
+            DependencyChange::Changed {
+                previous: dep("serde", "^1.0.100", DependencyKind::Normal),
+                current: dep("serde", "^1.0.150", DependencyKind::Normal),
+            },
+            DependencyChange::Changed {
+                previous: dep("cc", "^1", DependencyKind::Normal),
+                current: dep("cc", "^1", DependencyKind::Build),
+            },
+            DependencyChange::Added(dep("anyhow", "1", DependencyKind::Normal)),
+            DependencyChange::Removed(dep("log", "^0.4", DependencyKind::Normal)),
+        ],
+        "the kind of dependency is only used to match it if there are more than one"
+    );
+    assert_eq!(
+        changes[0].requirement_change(),
+        Some(RequirementChange::Tightened)
+    );
+    assert_eq!(
+        changes[1].requirement_change(),
+        None,
+        "only the kind changed"
+    );
+    assert_eq!(changes[2].requirement_change(), None);
+    assert!(previous.dependency_changes_since(&previous).is_empty());
+}
+
+#[test]
+fn requirement_changes() {
+    let change = |previous: &str, current: &str| {
+        let dep = |req: &str| Dependency {
+            name: "dep".into(),
+            required_version: req.into(),
+            features: Vec::new(),
+            optional: false,
+            default_features: true,
+            target: None,
+            kind: None,
+            package: None,
+        };
+        DependencyChange::Changed {
+            previous: dep(previous),
+            current: dep(current),
+        }
+        .requirement_change()
+    };
+    use RequirementChange::*;
+    for (previous, current, expected) in [
+        ("1.2", "^1.2.0", Some(Equivalent)),
+        ("~1", "1.*", Some(Equivalent)),
+        ("^1.2", ">=1.2", Some(Loosened)),
+        ("=1.2.3", "~1.2.3", Some(Loosened)),
+        ("^0.2", "^0", Some(Loosened)),
+        ("^1.2", "^1.3", Some(Tightened)),
+        ("^0.2", "^0.2.5", Some(Tightened)),
+        (">=1.2.3, <1.8.0", "~1.5", Some(Tightened)),
+        ("^0.0.3", "=0.0.3", Some(Equivalent)),
+        ("^1", "^2", Some(Other)),
+        ("^0.1", "^0.2", Some(Other)),
+        ("^1", "not a requirement", Some(Other)),
+        ("^1", "^1", None),
+    ] {
+        assert_eq!(
+            change(previous, current),
+            expected,
+            "{previous} -> {current}"
+        );
+    }
+}
+
+#[test]
+fn feature_changes_since_previous_version() {
+    let version = |features: &[(&str, &[&str])]| CrateVersion {
+        name: "test".into(),
+        features: features
+            .iter()
+            .map(|(name, enables)| {
+                (
+                    name.to_string(),
+                    enables.iter().map(|e| e.to_string()).collect(),
+                )
+            })
+            .collect(),
+        ..Default::default()
+    };
+    let previous = version(&[
+        ("default", &["std"]),
+        ("std", &[]),
+        ("serde", &["dep:serde", "std"]),
+        ("old", &[]),
+    ]);
+    let current = version(&[
+        ("default", &["std", "new"]),
+        ("std", &[]),
+        ("serde", &["std", "dep:serde"]),
+        ("new", &[]),
+    ]);
+
+    assert_eq!(
+        current.feature_changes_since(&previous),
+        [
+            FeatureChange::Changed {
+                name: "default".into(),
+                previous: vec!["std".into()],
+                current: vec!["std".into(), "new".into()],
+            },
+            FeatureChange::Added {
+                name: "new".into(),
+                enables: vec![],
+            },
+            FeatureChange::Removed {
+                name: "old".into(),
+                enables: vec![],
+            },
+        ],
+        "changes are sorted by name, and the order of enabled features doesn't matter"
+    );
+    assert!(previous.feature_changes_since(&previous).is_empty());
+}